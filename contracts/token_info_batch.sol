@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.13;
+
+// Source of `get_token_info_batch_code` in src/contract_modules/uniswap_v2/constants.rs
+//
+// Never deployed, the bot sends it with the abi encoded `address[]` appended as the data of an
+// `eth_call` without a `to`, and reads the returned "runtime code".
+//
+// The bytecode is the same loop assembled by hand (0xf3 bytes, no solc preamble). It reads the
+// length and the addresses straight from the code at the fixed offsets 0x113 and 0x133 (code length
+// + 0x20 / + 0x40, skipping the abi offset word), and writes the results from 0x40. `solc --bin` on
+// this file gives a longer init code returning the same data; keep the two in sync by hand.
+//
+// The returned data grows by about 320 bytes per token (672 at most), and is limited to 24576 bytes
+// by EIP-170, see `MAX_TOKENS_PER_BATCH` in data_collector/token_info.rs.
+contract TokenInfoBatch {
+    constructor(address[] memory tokens) {
+        assembly {
+            // (length, data padded to 32 bytes) of a call to `token` with no arguments, at `ptr`
+            // reverted calls and results over 192 bytes get a length of 0
+            function query(token, selector, ptr) -> next {
+                mstore(0, shl(224, selector))
+                let success := staticcall(50000, token, 0, 4, 0, 0)
+                let size := mul(returndatasize(), and(success, gt(0xc1, returndatasize())))
+                mstore(ptr, size)
+                returndatacopy(add(ptr, 0x20), 0, size)
+                next := add(ptr, add(0x20, shl(5, shr(5, add(size, 0x1f)))))
+            }
+
+            let start := mload(0x40)
+            let ptr := start
+            let length := mload(tokens)
+            for { let i := 0 } iszero(eq(i, length)) { i := add(i, 1) } {
+                let token := mload(add(tokens, shl(5, add(i, 1))))
+                ptr := query(token, 0x95d89b41, ptr) // symbol()
+                ptr := query(token, 0x06fdde03, ptr) // name()
+                ptr := query(token, 0x313ce567, ptr) // decimals()
+            }
+            return(start, sub(ptr, start))
+        }
+    }
+}
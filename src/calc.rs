@@ -1,22 +1,58 @@
 use ethers::types::I256;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::OnceLock;
 use tokio::sync::MutexGuard;
 
-// use crate::contract_modules::uniswap_v2::swap_math::get_amount_out;
 use crate::constants::WETH;
+use crate::contract_modules::uniswap_v2::get_uni_v2;
+use crate::contract_modules::uniswap_v2::swap_math::get_amount_out_with_tax;
 use crate::contract_modules::uniswap_v2::types::UniV2Pool;
+use crate::funding::{Capital, Funding};
 use crate::state::State;
 use ethers::types::{Address, U256};
 use std::cmp::Ordering;
 
-#[derive(Debug, Deserialize)]
+// Cycles in an execution plan at most
+pub const MAX_PLAN_CYCLES: usize = 5;
+// Candidates the best plan is searched over, up to 2^12 sets
+const SEARCH_CANDIDATES: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetPositiveCycle {
+    /// After the funding cost
     pub profit: I256,
     pub optimal_in: U256,
     pub swap_amounts: Vec<U256>,
     pub cycle_addresses: Vec<Address>,
+    /// Tokens traded, starting and ending with WETH
+    pub token_path: Vec<Address>,
+    /// One per pool of `cycle_addresses`
+    pub hops: Vec<CycleHop>,
+    /// Where `optimal_in` comes from
+    pub funding: Funding,
+    /// WETH paid for `funding`, already taken off `profit`
+    pub funding_cost: U256,
+}
+
+/// A swap of a cycle, with what's needed to encode it for the pool's router
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CycleHop {
+    pub pool: Address,
+    pub factory: Address,
+    // None for pools of a factory not listed in `UNISWAP_V2`
+    pub router: Option<Address>,
+    pub token_in: Address,
+    pub token_out: Address,
+    // token_in is the pool's token0
+    pub zero_for_one: bool,
+    // router fee, out of 10000
+    pub fee: U256,
+    // fee-on-transfer taxes of token_in when sold and of token_out when bought, out of 10000
+    pub tax_in: U256,
+    pub tax_out: U256,
 }
 
 impl Ord for NetPositiveCycle {
@@ -43,6 +79,7 @@ impl PartialEq for NetPositiveCycle {
 pub fn find_optimal_cycles(
     state: &MutexGuard<State>,
     affected_pairs: Option<Vec<Address>>,
+    capital: &Capital,
 ) -> Vec<NetPositiveCycle> {
     let mut pointers: Vec<&Vec<crate::state::IndexedPair>> = Vec::new();
 
@@ -64,43 +101,235 @@ pub fn find_optimal_cycles(
     let mut net_profit_cycles = Vec::new();
 
     let weth = Address::from_str(WETH).unwrap();
+    let routers = get_routers();
     for cycle in pointers {
         let pairs = cycle
             .iter()
             .filter_map(|pair| state.pairs_mapping.get(&pair.address))
             .collect::<Vec<&RefCell<UniV2Pool>>>();
 
-        let pairs_clone = pairs.clone();
-        let profit_function =
-            move |amount_in: U256| -> I256 { get_profit(weth, amount_in, &pairs_clone) };
+        if let Some(net_positive_cycle) = optimize_cycle(weth, &pairs, routers, capital) {
+            net_profit_cycles.push(net_positive_cycle);
+        }
+    }
+
+    select_cycles(state, net_profit_cycles, capital, MAX_PLAN_CYCLES)
+}
+
+/// Execution plan out of profitable `candidates`, in execution order
+///
+/// Cycles sharing a pool can't both execute, the plan is the set of at most `max_cycles` cycles
+/// without a pool in common that makes the most in total. The set is searched exhaustively over
+/// the `SEARCH_CANDIDATES` most profitable candidates, which keeps the search small on every
+/// block, the others only fill the room left in order of profit. Inventory spent by a cycle
+/// isn't there for the next ones, each is sized again on what's left and dropped if no longer
+/// profitable
+pub fn select_cycles(
+    state: &State,
+    mut candidates: Vec<NetPositiveCycle>,
+    capital: &Capital,
+    max_cycles: usize,
+) -> Vec<NetPositiveCycle> {
+    // highest profit first, the same cycle is found through each of its pools
+    candidates.sort();
+    let mut seen = HashSet::new();
+    candidates.retain(|cycle| seen.insert(cycle.cycle_addresses.clone()));
+
+    let searched = candidates.len().min(SEARCH_CANDIDATES);
+    let mut selected = best_plan(&candidates[..searched], max_cycles, capital.inventory);
+    for (index, cycle) in candidates.iter().enumerate().skip(searched) {
+        if selected.len() >= max_cycles {
+            break;
+        }
+        if !selected
+            .iter()
+            .any(|selected| shares_pool(&candidates[*selected], cycle))
+        {
+            selected.push(index);
+        }
+    }
+
+    let weth = Address::from_str(WETH).unwrap();
+    let mut capital = capital.clone();
+    let mut plan: Vec<NetPositiveCycle> = Vec::new();
+    for index in selected {
+        let pairs = match get_cycle_pairs(state, &candidates[index].cycle_addresses) {
+            Some(d) => d,
+            None => continue,
+        };
+        let cycle = match optimize_cycle(weth, &pairs, get_routers(), &capital) {
+            Some(d) => d,
+            None => continue,
+        };
+        if cycle.funding == Funding::Inventory {
+            capital.inventory = capital.inventory.saturating_sub(cycle.optimal_in);
+        }
+        plan.push(cycle);
+    }
+
+    plan
+}
+
+// Indexes of the cycles without a pool in common, at most `max_cycles`, making the most in
+// total out of `candidates` (highest profit first). Inventory funded ones share `inventory`
+fn best_plan(candidates: &[NetPositiveCycle], max_cycles: usize, inventory: U256) -> Vec<usize> {
+    let mut best = (I256::zero(), Vec::new());
+    search_plan(
+        candidates,
+        0,
+        max_cycles,
+        inventory,
+        &mut (I256::zero(), Vec::new()),
+        &mut best,
+    );
+    best.1
+}
+
+// Depth first over the candidates from `next` on that fit with `current`, keeps the best
+// total profit found in `best`
+fn search_plan(
+    candidates: &[NetPositiveCycle],
+    next: usize,
+    max_cycles: usize,
+    inventory: U256,
+    current: &mut (I256, Vec<usize>),
+    best: &mut (I256, Vec<usize>),
+) {
+    if current.0 > best.0 {
+        *best = current.clone();
+    }
+
+    // the most the candidates left could add, they are sorted by profit
+    let room = max_cycles.saturating_sub(current.1.len());
+    let bound = candidates[next..]
+        .iter()
+        .take(room)
+        .fold(current.0, |total, cycle| total.saturating_add(cycle.profit));
+    if room == 0 || bound <= best.0 {
+        return;
+    }
+
+    for (index, cycle) in candidates.iter().enumerate().skip(next) {
+        let spent = match cycle.funding {
+            Funding::Inventory => cycle.optimal_in,
+            _ => U256::zero(),
+        };
+        let conflicts = current
+            .1
+            .iter()
+            .any(|taken| shares_pool(&candidates[*taken], cycle));
+        if conflicts || spent > inventory {
+            continue;
+        }
+
+        current.0 = current.0.saturating_add(cycle.profit);
+        current.1.push(index);
+        search_plan(
+            candidates,
+            index + 1,
+            max_cycles,
+            inventory - spent,
+            current,
+            best,
+        );
+        current.1.pop();
+        current.0 = current.0.saturating_sub(cycle.profit);
+    }
+}
+
+fn shares_pool(a: &NetPositiveCycle, b: &NetPositiveCycle) -> bool {
+    a.cycle_addresses
+        .iter()
+        .any(|pool| b.cycle_addresses.contains(pool))
+}
+
+/// Best sized trade of WETH through `pairs` over every funding `capital` offers,
+/// `None` if none makes a profit after its cost
+///
+/// On equal profit the earlier of `Capital::options` wins, inventory before borrowing
+pub fn optimize_cycle(
+    weth: Address,
+    pairs: &[&RefCell<UniV2Pool>],
+    routers: &HashMap<Address, Address>,
+    capital: &Capital,
+) -> Option<NetPositiveCycle> {
+    let pairs = pairs.to_vec();
+
+    // (profit after cost, funding, amount in, cost)
+    let mut best: Option<(I256, Funding, U256, U256)> = None;
+    for (funding, max_in) in capital.options() {
+        let net_profit = |amount_in: U256| -> I256 {
+            let cost = capital.cost(funding, amount_in);
+            let cost = I256::try_from(cost).unwrap_or(I256::MAX);
+            get_profit(weth, amount_in, &pairs).saturating_sub(cost)
+        };
 
         let optimal = maximize_profit(
             U256::one(),
-            U256::from_dec_str("10000000000000000000000").unwrap(),
+            max_in,
             U256::from_dec_str("10").unwrap(),
-            profit_function,
+            net_profit,
         );
+        let profit = net_profit(optimal);
 
-        let (profit, swap_amounts) = get_profit_with_amount(weth, optimal, &pairs);
-
-        let mut cycle_internal = Vec::new();
-        for pair in pairs {
-            cycle_internal.push(pair.borrow().address);
+        let is_better = match best {
+            Some((best_profit, ..)) => profit > best_profit,
+            None => true,
+        };
+        if is_better {
+            best = Some((profit, funding, optimal, capital.cost(funding, optimal)));
         }
+    }
 
-        if profit > I256::one() {
-            let net_positive_cycle = NetPositiveCycle {
-                profit,
-                optimal_in: optimal,
-                cycle_addresses: cycle_internal,
-                swap_amounts,
-            };
-            net_profit_cycles.push(net_positive_cycle);
-        }
+    let (profit, funding, optimal, funding_cost) = best?;
+    if profit <= I256::one() {
+        return None;
+    }
+    let (_, swap_amounts) = get_profit_with_amount(weth, optimal, &pairs);
+
+    let mut cycle_internal = Vec::new();
+    for pair in &pairs {
+        cycle_internal.push(pair.borrow().address);
     }
+    let (token_path, hops) = get_cycle_hops(weth, &pairs, routers);
+
+    Some(NetPositiveCycle {
+        profit,
+        optimal_in: optimal,
+        cycle_addresses: cycle_internal,
+        swap_amounts,
+        token_path,
+        hops,
+        funding,
+        funding_cost,
+    })
+}
+
+// Factory -> router of every known dex, `UNISWAP_V2` is only read once
+fn get_routers() -> &'static HashMap<Address, Address> {
+    static ROUTERS: OnceLock<HashMap<Address, Address>> = OnceLock::new();
+    ROUTERS.get_or_init(|| {
+        get_uni_v2()
+            .into_iter()
+            .map(|dex| (dex.factory, dex.router))
+            .collect()
+    })
+}
 
-    net_profit_cycles.sort();
-    net_profit_cycles.into_iter().take(5).collect()
+// Pools of `cycle_addresses` in `state`, `None` if one isn't there anymore
+fn get_cycle_pairs<'a>(
+    state: &'a State,
+    cycle_addresses: &[Address],
+) -> Option<Vec<&'a RefCell<UniV2Pool>>> {
+    cycle_addresses
+        .iter()
+        .map(|address| {
+            state
+                .address_mapping
+                .get(address)
+                .and_then(|index| state.pairs_mapping.get(index))
+        })
+        .collect()
 }
 
 // find optimal input before uni fees eats away our profits
@@ -144,15 +373,21 @@ pub fn get_profit(token_in: Address, amount_in: U256, pairs: &Vec<&RefCell<UniV2
     let mut token_in = token_in;
     for pair in pairs {
         let pair = pair.borrow();
-        let fees;
-        let (reserve0, reserve1) = if pair.token0 == token_in {
-            fees = pair.fees1;
-            (pair.reserve0, pair.reserve1)
+        // we sell token in to the pair and buy token out from it
+        let (reserve0, reserve1, tax_in, tax_out) = if pair.token0 == token_in {
+            (pair.reserve0, pair.reserve1, pair.fees0.sell, pair.fees1.buy)
         } else {
-            fees = pair.fees0;
-            (pair.reserve1, pair.reserve0)
+            (pair.reserve1, pair.reserve0, pair.fees1.sell, pair.fees0.buy)
         };
-        amount_out = get_amount_out(amount_out, reserve0, reserve1, fees, pair.router_fee);
+        amount_out = get_amount_out_with_tax(
+            amount_out,
+            reserve0,
+            reserve1,
+            pair.router_fee,
+            tax_in,
+            tax_out,
+        )
+        .unwrap_or_default();
         token_in = if pair.token0 == token_in {
             pair.token1
         } else {
@@ -174,15 +409,21 @@ pub fn get_profit_with_amount(
     amounts.push(amount_in);
     for pair in pairs {
         let pair = pair.borrow();
-        let fees;
-        let (reserve0, reserve1) = if pair.token0 == token_in {
-            fees = pair.fees1;
-            (pair.reserve0, pair.reserve1)
+        // we sell token in to the pair and buy token out from it
+        let (reserve0, reserve1, tax_in, tax_out) = if pair.token0 == token_in {
+            (pair.reserve0, pair.reserve1, pair.fees0.sell, pair.fees1.buy)
         } else {
-            fees = pair.fees0;
-            (pair.reserve1, pair.reserve0)
+            (pair.reserve1, pair.reserve0, pair.fees1.sell, pair.fees0.buy)
         };
-        amount_out = get_amount_out(amount_out, reserve0, reserve1, fees, pair.router_fee);
+        amount_out = get_amount_out_with_tax(
+            amount_out,
+            reserve0,
+            reserve1,
+            pair.router_fee,
+            tax_in,
+            tax_out,
+        )
+        .unwrap_or_default();
         amounts.push(amount_out);
         token_in = if pair.token0 == token_in {
             pair.token1
@@ -197,23 +438,205 @@ pub fn get_profit_with_amount(
     )
 }
 
-// We don't want overflow / underflow at runtime + need to be a bit fast
-pub fn get_amount_out(
-    a_in: U256,
-    reserve_in: U256,
-    reserve_out: U256,
-    fees: U256,
-    router_fee: U256,
-) -> U256 {
-    if a_in == U256::zero() {
-        return U256::zero();
+/// Token path and hops of swapping `token_in` through `pairs`
+/// `routers` maps a factory to its router
+pub fn get_cycle_hops(
+    token_in: Address,
+    pairs: &[&RefCell<UniV2Pool>],
+    routers: &HashMap<Address, Address>,
+) -> (Vec<Address>, Vec<CycleHop>) {
+    let mut token_in = token_in;
+    let mut token_path = Vec::with_capacity(pairs.len() + 1);
+    let mut hops = Vec::with_capacity(pairs.len());
+    token_path.push(token_in);
+    for pair in pairs {
+        let pair = pair.borrow();
+        let zero_for_one = pair.token0 == token_in;
+        let (token_out, tax_in, tax_out) = if zero_for_one {
+            (pair.token1, pair.fees0.sell, pair.fees1.buy)
+        } else {
+            (pair.token0, pair.fees1.sell, pair.fees0.buy)
+        };
+
+        hops.push(CycleHop {
+            pool: pair.address,
+            factory: pair.factory,
+            router: routers.get(&pair.factory).copied(),
+            token_in,
+            token_out,
+            zero_for_one,
+            fee: pair.router_fee,
+            tax_in,
+            tax_out,
+        });
+        token_path.push(token_out);
+        token_in = token_out;
     }
-    let a_in_with_fee = a_in.saturating_mul(router_fee);
-    let a_out = a_in_with_fee.saturating_mul(reserve_out)
-        / U256::from(10000)
-            .saturating_mul(reserve_in)
-            .saturating_add(a_in_with_fee);
 
-    a_out - a_out.saturating_mul(fees) / U256::from(10000)
+    (token_path, hops)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract_modules::uniswap_v2::ban_list::BanList;
+    use crate::contract_modules::uniswap_v2::types::{TokenSafety, TokenTax};
+    use crate::funding::FlashLoanProvider;
+    use crate::test_utils::{pool, weth_pool};
+
+    #[test]
+    fn plan_takes_the_most_profitable_set_without_shared_pools() {
+        let token = Address::from_low_u64_be(100);
+        let pools = [
+            weth_pool(1, token, 1200),
+            weth_pool(2, token, 1000),
+            weth_pool(3, token, 1020),
+            weth_pool(4, token, 1180),
+        ];
+        let state = State::new_state(&pools, &BanList::default());
+        let weth = Address::from_str(WETH).unwrap();
+        let routers = HashMap::new();
+        let capital = Capital {
+            flash_swaps: true,
+            ..Capital::default()
+        };
+
+        // buy the token where it's cheap, sell it back where it's expensive
+        let candidates: Vec<NetPositiveCycle> = [[1, 2], [1, 3], [4, 2]]
+            .iter()
+            .map(|cycle| {
+                let addresses = cycle.map(Address::from_low_u64_be);
+                let pairs = get_cycle_pairs(&state, &addresses).unwrap();
+                optimize_cycle(weth, &pairs, &routers, &capital).unwrap()
+            })
+            .collect();
+        // the best cycle shares a pool with each of the two others
+        assert!(candidates[0].profit > candidates[1].profit);
+        assert!(candidates[0].profit > candidates[2].profit);
+        let both = candidates[1].profit + candidates[2].profit;
+        assert!(both > candidates[0].profit);
+
+        let mut duplicated = candidates.clone();
+        duplicated.push(candidates[1].clone());
+        let plan = select_cycles(&state, duplicated, &capital, MAX_PLAN_CYCLES);
+
+        let addresses: Vec<_> = plan.iter().map(|cycle| &cycle.cycle_addresses).collect();
+        assert_eq!(
+            addresses,
+            [&candidates[2].cycle_addresses, &candidates[1].cycle_addresses]
+        );
+        assert_eq!(plan[0].profit + plan[1].profit, both);
+
+        // with room for one only the best cycle is left
+        let plan = select_cycles(&state, candidates.clone(), &capital, 1);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].cycle_addresses, candidates[0].cycle_addresses);
+    }
+
+    #[test]
+    fn inventory_spent_is_not_there_for_the_next_cycle() {
+        let token = Address::from_low_u64_be(100);
+        let pools = [
+            weth_pool(1, token, 1200),
+            weth_pool(2, token, 1000),
+            weth_pool(3, token, 1190),
+            weth_pool(4, token, 1010),
+        ];
+        let state = State::new_state(&pools, &BanList::default());
+        let weth = Address::from_str(WETH).unwrap();
+        let mut capital = Capital {
+            inventory: U256::exp10(24),
+            ..Capital::default()
+        };
+
+        let candidates: Vec<NetPositiveCycle> = [[1, 2], [3, 4]]
+            .iter()
+            .map(|cycle| {
+                let addresses = cycle.map(Address::from_low_u64_be);
+                let pairs = get_cycle_pairs(&state, &addresses).unwrap();
+                optimize_cycle(weth, &pairs, &HashMap::new(), &capital).unwrap()
+            })
+            .collect();
+        // only enough for the first one
+        capital.inventory = candidates[0].optimal_in;
+
+        let plan = select_cycles(&state, candidates.clone(), &capital, MAX_PLAN_CYCLES);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].cycle_addresses, candidates[0].cycle_addresses);
+    }
+
+    #[test]
+    fn cheapest_funding_is_chosen() {
+        let token = Address::from_low_u64_be(100);
+        let pools = [weth_pool(1, token, 1000), weth_pool(2, token, 1100)];
+        let state = State::new_state(&pools, &BanList::default());
+        let weth = Address::from_str(WETH).unwrap();
+        let pairs = get_cycle_pairs(&state, &[pools[1].address, pools[0].address]).unwrap();
+        let provider = FlashLoanProvider {
+            address: Address::from_low_u64_be(7),
+            fee: U256::from(5),
+        };
+        let mut capital = Capital {
+            inventory: U256::exp10(22),
+            flash_swaps: false,
+            flash_loans: vec![provider],
+        };
+
+        // more inventory than the cycle needs
+        let inventory = optimize_cycle(weth, &pairs, &HashMap::new(), &capital).unwrap();
+        assert_eq!(inventory.funding, Funding::Inventory);
+        assert!(inventory.funding_cost.is_zero());
+
+        // too little of it, the loan's fee is worth paying
+        capital.inventory = inventory.optimal_in / 10;
+        let loan = optimize_cycle(weth, &pairs, &HashMap::new(), &capital).unwrap();
+        assert_eq!(
+            loan.funding,
+            Funding::FlashLoan {
+                provider: provider.address
+            }
+        );
+        assert_eq!(loan.funding_cost, provider.cost(loan.optimal_in));
+        let gross = I256::from_raw(*loan.swap_amounts.last().unwrap())
+            - I256::from_raw(loan.optimal_in);
+        assert_eq!(loan.profit, gross - I256::from_raw(loan.funding_cost));
+
+        // a flash swap costs nothing on top
+        capital.flash_swaps = true;
+        let flash_swap = optimize_cycle(weth, &pairs, &HashMap::new(), &capital).unwrap();
+        assert_eq!(flash_swap.funding, Funding::FlashSwap);
+        assert!(flash_swap.profit > loan.profit);
+    }
+
+    #[test]
+    fn hops_follow_the_cycle_across_factories() {
+        let [token0, token1] = [100, 200].map(Address::from_low_u64_be);
+        let taxed = |address, factory| {
+            RefCell::new(UniV2Pool {
+                factory: Address::from_low_u64_be(factory),
+                fees1: TokenTax {
+                    sell: U256::from(100),
+                    ..TokenTax::default()
+                },
+                safety1: TokenSafety::Taxed,
+                ..pool(address, token0, token1, U256::zero(), U256::zero())
+            })
+        };
+        let (first, second) = (taxed(10, 1), taxed(11, 2));
+        let pairs = vec![&first, &second];
+        let routers = HashMap::from([(Address::from_low_u64_be(1), Address::from_low_u64_be(5))]);
+
+        let (token_path, hops) = get_cycle_hops(Address::from_low_u64_be(100), &pairs, &routers);
+
+        assert_eq!(
+            token_path,
+            [100, 200, 100].map(Address::from_low_u64_be).to_vec()
+        );
+        assert!(hops[0].zero_for_one);
+        assert_eq!(hops[0].router, Some(Address::from_low_u64_be(5)));
+        assert!(!hops[1].zero_for_one);
+        assert_eq!(hops[1].router, None);
+        assert_eq!(hops[1].factory, Address::from_low_u64_be(2));
+        assert_eq!(hops[1].tax_in, U256::from(100));
+    }
+}
@@ -0,0 +1,155 @@
+use ethers::prelude::*;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Mutex;
+
+use super::state_fetcher::StateFetcher;
+
+/// An account as the node would serve it, slots left out read as zero
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureAccount {
+    pub balance: U256,
+    pub nonce: U256,
+    pub code: Bytes,
+    pub storage: BTreeMap<H256, H256>,
+}
+
+/// State of a single block held in memory, so that forks run offline
+///
+/// Accounts left out are empty, like on a node. Saved as JSON, see `RecordingFetcher` to
+/// record one from a live node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureState {
+    // block the state was recorded at, left to whoever records it
+    #[serde(default)]
+    pub block: U64,
+    pub accounts: BTreeMap<Address, FixtureAccount>,
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+impl FixtureState {
+    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
+        let file = File::create(file_path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(file_path: &str) -> std::io::Result<FixtureState> {
+        let reader = BufReader::new(File::open(file_path)?);
+        let fixture = serde_json::from_reader(reader)?;
+        Ok(fixture)
+    }
+}
+
+// the fixture is taken as the state at whatever block is asked for
+impl StateFetcher for FixtureState {
+    type Error = Infallible;
+
+    fn basic(
+        &self,
+        address: Address,
+        _block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<(U256, U256, Bytes), Self::Error>> {
+        let account = self.accounts.get(&address).cloned().unwrap_or_default();
+        Box::pin(async move { Ok((account.balance, account.nonce, account.code)) })
+    }
+
+    fn storage(
+        &self,
+        address: Address,
+        slot: H256,
+        _block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<H256, Self::Error>> {
+        let value = self
+            .accounts
+            .get(&address)
+            .and_then(|account| account.storage.get(&slot))
+            .copied()
+            .unwrap_or_default();
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn block_hash(&self, number: u64) -> BoxFuture<'_, Result<Option<H256>, Self::Error>> {
+        let hash = self.block_hashes.get(&number).copied();
+        Box::pin(async move { Ok(hash) })
+    }
+}
+
+/// Fetches from `inner` and keeps everything it served, to be saved as a fixture
+pub struct RecordingFetcher<F> {
+    inner: F,
+    recorded: Mutex<FixtureState>,
+}
+
+impl<F: StateFetcher> RecordingFetcher<F> {
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            recorded: Mutex::new(FixtureState::default()),
+        }
+    }
+
+    /// What was fetched so far
+    pub fn fixture(&self) -> FixtureState {
+        self.recorded.lock().unwrap().clone()
+    }
+}
+
+impl<F: StateFetcher> StateFetcher for RecordingFetcher<F> {
+    type Error = F::Error;
+
+    fn basic(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<(U256, U256, Bytes), Self::Error>> {
+        Box::pin(async move {
+            let (balance, nonce, code) = self.inner.basic(address, block).await?;
+
+            let mut recorded = self.recorded.lock().unwrap();
+            let account = recorded.accounts.entry(address).or_default();
+            account.balance = balance;
+            account.nonce = nonce;
+            account.code = code.clone();
+
+            Ok((balance, nonce, code))
+        })
+    }
+
+    fn storage(
+        &self,
+        address: Address,
+        slot: H256,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<H256, Self::Error>> {
+        Box::pin(async move {
+            let value = self.inner.storage(address, slot, block).await?;
+
+            let mut recorded = self.recorded.lock().unwrap();
+            let account = recorded.accounts.entry(address).or_default();
+            account.storage.insert(slot, value);
+
+            Ok(value)
+        })
+    }
+
+    fn block_hash(&self, number: u64) -> BoxFuture<'_, Result<Option<H256>, Self::Error>> {
+        Box::pin(async move {
+            let hash = self.inner.block_hash(number).await?;
+
+            if let Some(hash) = hash {
+                self.recorded
+                    .lock()
+                    .unwrap()
+                    .block_hashes
+                    .insert(number, hash);
+            }
+
+            Ok(hash)
+        })
+    }
+}
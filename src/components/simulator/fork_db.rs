@@ -27,6 +27,31 @@ impl ForkDB {
         Self { backend, db }
     }
 
+    // Insert storage into this fork only
+    pub fn insert_account_storage(
+        &mut self,
+        address: rAddress,
+        slot: rU256,
+        value: rU256,
+    ) -> DatabaseResult<()> {
+        if self.db.accounts.get(&address).is_none() {
+            // set basic info as its missing
+            let info = match self.do_get_basic(address) {
+                Ok(i) => i,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(info) = info {
+                self.db.insert_account_info(address, info);
+            }
+        }
+        self.db
+            .insert_account_storage(address, slot, value)
+            .unwrap();
+
+        Ok(())
+    }
+
     fn do_get_basic(&self, address: rAddress) -> DatabaseResult<Option<AccountInfo>> {
         tokio::task::block_in_place(|| {
             let (sender, rx) = oneshot_channel();
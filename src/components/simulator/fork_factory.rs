@@ -5,14 +5,15 @@ use super::{
     database_error::DatabaseResult,
     fork_db::ForkDB,
     global_backend::{BackendFetchRequest, GlobalBackend},
+    state_fetcher::StateFetcher,
 };
-use ethers::prelude::*;
 use ethers::types::BlockId;
 use futures::channel::mpsc::{channel, Sender};
 use revm::{
     db::{CacheDB, EmptyDB},
     primitives::{AccountInfo, Address as rAddress, U256 as rU256},
 };
+use tokio_util::sync::CancellationToken;
 
 /// Type that setups up backend and clients to talk to backend
 /// each client is an own evm instance but we cache request results
@@ -27,19 +28,19 @@ impl ForkFactory {
     // Create a new `ForkFactory` instance
     //
     // Arguments:
-    // * `provider`: Websocket client used for fetching missing state
+    // * `fetcher`: Client used for fetching missing state, any provider or a `FixtureState`
     // * `initial_db`: Database with initial state
     // * `fork_block`: Block to fork from when making rpc calls
     //
     // Returns:
     // `(ForkFactory, GlobalBackend)`: ForkFactory instance and the GlobalBackend it talks to
-    fn new(
-        provider: Arc<Provider<Ws>>,
+    fn new<F: StateFetcher>(
+        fetcher: Arc<F>,
         initial_db: CacheDB<EmptyDB>,
         fork_block: Option<BlockId>,
-    ) -> (Self, GlobalBackend) {
+    ) -> (Self, GlobalBackend<F>) {
         let (backend, backend_rx) = channel(1);
-        let handler = GlobalBackend::new(backend_rx, fork_block, provider, initial_db.clone());
+        let handler = GlobalBackend::new(backend_rx, fork_block, fetcher, initial_db.clone());
         (
             Self {
                 backend,
@@ -60,15 +61,19 @@ impl ForkFactory {
     }
 
     // Create a new sandbox environment with backend running on own thread
-    pub fn new_sandbox_factory(
-        provider: Arc<Provider<Ws>>,
+    //
+    // The backend thread runs until every fork is dropped or `shutdown` is cancelled, requests
+    // made after that fail
+    pub fn new_sandbox_factory<F: StateFetcher>(
+        fetcher: Arc<F>,
         initial_db: CacheDB<EmptyDB>,
         fork_block: Option<BlockId>,
+        shutdown: CancellationToken,
     ) -> Self {
-        let (shared, handler) = Self::new(provider, initial_db, fork_block);
+        let (shared, handler) = Self::new(fetcher, initial_db, fork_block);
 
         // spawn a light-weight thread with a thread-local async runtime just for
-        // sending and receiving data from the remote client
+        // sending and receiving data from the fetcher
         let _ = std::thread::Builder::new()
             .name("fork-backend-thread".to_string())
             .spawn(move || {
@@ -77,7 +82,12 @@ impl ForkFactory {
                     .build()
                     .expect("failed to create fork-backend-thread tokio runtime");
 
-                rt.block_on(async move { handler.await });
+                rt.block_on(async move {
+                    tokio::select! {
+                        _ = handler => {}
+                        _ = shutdown.cancelled() => {}
+                    }
+                });
             })
             .expect("failed to spawn backendhandler thread");
 
@@ -1,7 +1,6 @@
 // credit to Foundry's SharedBackend implmenetation:
 // https://github.com/foundry-rs/foundry/blob/master/evm/src/executor/fork/backend.rs
 use ethers::{
-    providers::{Middleware, Provider, ProviderError, Ws},
     types::{Address, BigEndianHash, BlockId, H256, U256},
     utils::keccak256,
 };
@@ -26,22 +25,23 @@ use std::{
 };
 
 use super::database_error::{DatabaseError, DatabaseResult};
+use super::state_fetcher::StateFetcher;
 
 // **incoming req and outcoming req handled using revm types
-// all logic internal to this module handled using ethers types (because of provider)
+// all logic internal to this module handled using ethers types (because of the fetcher)
 type AccountInfoSender = OneshotSender<DatabaseResult<AccountInfo>>;
 type StorageSender = OneshotSender<DatabaseResult<rU256>>;
 type BlockHashSender = OneshotSender<DatabaseResult<B256>>;
 
-type BasicFuture<Err> =
-    Pin<Box<dyn Future<Output = (Result<(rU256, u64, rBytes), Err>, rAddress)> + Send>>;
+// the fetcher's error, or a nonce revm can't hold
+type BasicFuture = Pin<Box<dyn Future<Output = (Result<(rU256, u64, rBytes)>, rAddress)> + Send>>;
 type StorageFuture<Err> =
     Pin<Box<dyn Future<Output = (Result<rU256, Err>, rAddress, rU256)> + Send>>;
 type BlockHashFuture<Err> = Pin<Box<dyn Future<Output = (Result<B256, Err>, rU256)> + Send>>;
 
-/// Request variants that are executed by the provider
+/// Request variants that are executed by the fetcher
 enum FetchRequestFuture<Err> {
-    Basic(BasicFuture<Err>),
+    Basic(BasicFuture),
     Storage(StorageFuture<Err>),
     BlockHash(BlockHashFuture<Err>),
 }
@@ -57,15 +57,15 @@ pub enum BackendFetchRequest {
     BlockHash(rU256, BlockHashSender),
 }
 
-/// Holds db and provdier_db to fallback on so that
+/// Holds db and the fetcher to fallback on so that
 /// we can make rpc calls for missing data
-pub struct GlobalBackend {
+pub struct GlobalBackend<F: StateFetcher> {
     db: CacheDB<EmptyDB>,
     // used to make calls for missing data
-    provider: Arc<Provider<Ws>>,
+    fetcher: Arc<F>,
     block_num: Option<BlockId>,
     /// Requests currently in progress
-    pending_requests: Vec<FetchRequestFuture<ProviderError>>,
+    pending_requests: Vec<FetchRequestFuture<F::Error>>,
     /// Listeners that wait for a `get_account` related response
     account_requests: HashMap<rAddress, Vec<AccountInfoSender>>,
     /// Listeners that wait for a `get_storage_at` response
@@ -78,17 +78,17 @@ pub struct GlobalBackend {
     queued_requests: VecDeque<BackendFetchRequest>,
 }
 
-impl GlobalBackend {
+impl<F: StateFetcher> GlobalBackend<F> {
     // not so elegeant but create sim env from state diffs
     pub fn new(
         rx: Receiver<BackendFetchRequest>,
         block_num: Option<BlockId>,
-        provider: Arc<Provider<Ws>>,
+        fetcher: Arc<F>,
         initial_db: CacheDB<EmptyDB>,
     ) -> Self {
         Self {
             db: initial_db,
-            provider,
+            fetcher,
             block_num,
             pending_requests: Default::default(),
             account_requests: Default::default(),
@@ -103,7 +103,7 @@ impl GlobalBackend {
     ///
     /// We always check:
     ///  1. if the requested value is already stored in the cache, then answer the sender
-    ///  2. otherwise, fetch it via the provider but check if a request for that value is already in
+    ///  2. otherwise, fetch it via the fetcher but check if a request for that value is already in
     /// progress (e.g. another Sender just requested the same account)
     fn on_request(&mut self, req: BackendFetchRequest) {
         match req {
@@ -147,18 +147,20 @@ impl GlobalBackend {
             }
             Entry::Vacant(entry) => {
                 entry.insert(vec![listener]);
-                let provider = self.provider.clone();
+                let fetcher = self.fetcher.clone();
                 let block_num = self.block_num;
                 let fut = Box::pin(async move {
                     // convert from revm to ethers
                     let address_ethers: Address = address.0.into();
 
-                    let balance = provider.get_balance(address_ethers, block_num);
-                    let nonce = provider.get_transaction_count(address_ethers, block_num);
-                    let code = provider.get_code(address_ethers, block_num);
-                    let resp = tokio::try_join!(balance, nonce, code);
+                    let resp = fetcher.basic(address_ethers, block_num).await;
 
-                    let resp = resp.map(|(b, n, c)| (b.into(), n.as_u64(), c.0));
+                    let resp = resp.map_err(eyre::Error::new).and_then(|(b, n, c)| {
+                        if n > U256::from(u64::MAX) {
+                            eyre::bail!("nonce {} of {:?} overflows u64", n, address_ethers);
+                        }
+                        Ok((b.into(), n.as_u64(), c.0))
+                    });
                     (resp, address)
                 });
                 self.pending_requests.push(FetchRequestFuture::Basic(fut));
@@ -174,16 +176,14 @@ impl GlobalBackend {
             }
             Entry::Vacant(entry) => {
                 entry.insert(vec![listener]);
-                let provider = self.provider.clone();
+                let fetcher = self.fetcher.clone();
                 let block_num = self.block_num;
                 let fut = Box::pin(async move {
                     // convert from revm to ethers type
                     let idx_ethers = H256::from_uint(&U256::from(idx));
                     let address_ethers: Address = address.0.into();
 
-                    let storage = provider
-                        .get_storage_at(address_ethers, idx_ethers, block_num)
-                        .await;
+                    let storage = fetcher.storage(address_ethers, idx_ethers, block_num).await;
                     let storage = storage.map(|storage| storage.into_uint());
 
                     // convert ethers types to revm types
@@ -204,16 +204,14 @@ impl GlobalBackend {
             }
             Entry::Vacant(entry) => {
                 entry.insert(vec![listener]);
-                let provider = self.provider.clone();
+                let fetcher = self.fetcher.clone();
                 let fut = Box::pin(async move {
                     // convert from revm to ethers type
                     let number_ethers: u64 = U256::from(number).as_u64();
-                    let block = provider.get_block(number_ethers).await;
+                    let block_hash = fetcher.block_hash(number_ethers).await;
 
-                    let block_hash = match block {
-                        Ok(Some(block)) => Ok(block
-                            .hash
-                            .expect("empty block hash on mined block, this should never happen")),
+                    let block_hash = match block_hash {
+                        Ok(Some(block_hash)) => Ok(block_hash),
                         Ok(None) => {
                             // if no block was returned then the block does not exist, in which case
                             // we return empty hash
@@ -233,7 +231,7 @@ impl GlobalBackend {
     }
 }
 
-impl Future for GlobalBackend {
+impl<F: StateFetcher> Future for GlobalBackend<F> {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -244,7 +242,7 @@ impl Future for GlobalBackend {
                 pin.on_request(req)
             }
 
-            // receive new requests to delegate to the underlying provider
+            // receive new requests to delegate to the underlying fetcher
             loop {
                 match Pin::new(&mut pin.incoming).poll_next(cx) {
                     Poll::Ready(Some(req)) => {
@@ -267,7 +265,7 @@ impl Future for GlobalBackend {
                             let (balance, nonce, code) = match resp {
                                 Ok(res) => res,
                                 Err(err) => {
-                                    let err = Arc::new(eyre::Error::new(err));
+                                    let err = Arc::new(err);
                                     if let Some(listeners) = pin.account_requests.remove(&addr) {
                                         listeners.into_iter().for_each(|l| {
                                             let _ = l.send(Err(DatabaseError::GetAccount(
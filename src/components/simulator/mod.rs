@@ -4,5 +4,8 @@ pub use database_error::DatabaseError;
 pub mod global_backend;
 pub use global_backend::*;
 
+pub mod fixture;
 pub mod fork_db;
 pub mod fork_factory;
+pub mod slot_finder;
+pub mod state_fetcher;
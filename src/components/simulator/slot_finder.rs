@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use ethers::abi::{self, parse_abi};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use revm::{
+    interpreter::{opcode, InstructionResult, Interpreter},
+    primitives::{Address as rAddress, ExecutionResult, Output, TransactTo, B160, U256 as rU256},
+    Database, EVMData, Inspector,
+};
+
+use super::{fork_db::ForkDB, fork_factory::ForkFactory};
+
+// SHA3 in older specs
+const KECCAK256: u8 = 0x20;
+// Struct members a mapping entry may hold before the balance or allowance
+const MAX_STRUCT_OFFSET: u64 = 16;
+
+/// How a compiler lays out `mapping(address => ...)` in storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingLayout {
+    /// keccak256(key . slot)
+    Solidity,
+    /// keccak256(slot . key)
+    Vyper,
+}
+
+/// Storage slot of a mapping declaration and how its keys are hashed
+///
+/// `slot` may be anywhere in storage, eg: in an ERC-7201 namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappingSlot {
+    pub slot: U256,
+    pub layout: MappingLayout,
+    // position of the value in the struct an entry holds, 0 for a plain value
+    pub offset: U256,
+}
+
+impl MappingSlot {
+    /// Location of `mapping[key]`
+    pub fn location(&self, key: Address) -> U256 {
+        hash_key(self.slot, key, self.layout)
+            .overflowing_add(self.offset)
+            .0
+    }
+
+    /// Location of `mapping[key][key_after]`
+    pub fn nested_location(&self, key: Address, key_after: Address) -> U256 {
+        let inner = hash_key(self.slot, key, self.layout);
+        hash_key(inner, key_after, self.layout)
+            .overflowing_add(self.offset)
+            .0
+    }
+}
+
+/// ERC20 storage layout found for a token, `None` where it could not be found
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenSlots {
+    pub balance: Option<MappingSlot>,
+    pub allowance: Option<MappingSlot>,
+}
+
+/// Finds the `balanceOf` and `allowance` mappings of tokens by tracing the storage reads of those
+/// calls in revm, results are cached per token
+#[derive(Debug, Clone, Default)]
+pub struct SlotFinder {
+    cache: HashMap<Address, TokenSlots>,
+}
+
+impl SlotFinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Get (or find and cache) the slots of `token`
+    pub fn find(&mut self, token: Address, fork_factory: &ForkFactory, block: U64) -> TokenSlots {
+        if let Some(slots) = self.cache.get(&token) {
+            return *slots;
+        }
+
+        let slots = TokenSlots {
+            balance: find_balance_slot(token, fork_factory, block),
+            allowance: find_allowance_slot(token, fork_factory, block),
+        };
+        self.cache.insert(token, slots);
+
+        slots
+    }
+
+    /// Storage location of `balanceOf(owner)`
+    pub fn balance_location(
+        &mut self,
+        token: Address,
+        owner: Address,
+        fork_factory: &ForkFactory,
+        block: U64,
+    ) -> Option<U256> {
+        let slot = self.find(token, fork_factory, block).balance?;
+        Some(slot.location(owner))
+    }
+
+    /// Storage location of `allowance(owner, spender)`
+    pub fn allowance_location(
+        &mut self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        fork_factory: &ForkFactory,
+        block: U64,
+    ) -> Option<U256> {
+        let slot = self.find(token, fork_factory, block).allowance?;
+        Some(slot.nested_location(owner, spender))
+    }
+}
+
+fn find_balance_slot(
+    token: Address,
+    fork_factory: &ForkFactory,
+    block: U64,
+) -> Option<MappingSlot> {
+    let owner = probe_owner();
+    let data = erc20_contract().encode("balanceOf", owner).ok()?;
+
+    let (location, preimages) = find_location(token, data, fork_factory, block)?;
+    let (slot, layout, offset) = match_entry(location, owner, &preimages, MAX_STRUCT_OFFSET)?;
+    Some(MappingSlot {
+        slot,
+        layout,
+        offset,
+    })
+}
+
+fn find_allowance_slot(
+    token: Address,
+    fork_factory: &ForkFactory,
+    block: U64,
+) -> Option<MappingSlot> {
+    let (owner, spender) = (probe_owner(), probe_spender());
+    let data = erc20_contract()
+        .encode("allowance", (owner, spender))
+        .ok()?;
+
+    let (location, preimages) = find_location(token, data, fork_factory, block)?;
+    let (inner, layout, offset) = match_entry(location, spender, &preimages, MAX_STRUCT_OFFSET)?;
+    // the outer mapping holds the inner one itself, not in a struct
+    let (slot, outer_layout, _) = match_entry(inner, owner, &preimages, 1)?;
+    if outer_layout != layout {
+        return None;
+    }
+
+    Some(MappingSlot {
+        slot,
+        layout,
+        offset,
+    })
+}
+
+// Mapping entry of `key` that `location` is in, as (declaration slot, layout, offset), from the
+// hashes computed while tracing. Entries up to `max_offset - 1` slots into a struct are found
+fn match_entry(
+    location: U256,
+    key: Address,
+    preimages: &HashMap<U256, Vec<u8>>,
+    max_offset: u64,
+) -> Option<(U256, MappingLayout, U256)> {
+    let key = U256::from_big_endian(H256::from(key).as_bytes());
+
+    (0..max_offset).find_map(|offset| {
+        let offset = U256::from(offset);
+        let preimage = preimages.get(&location.checked_sub(offset)?)?;
+        if preimage.len() != 64 {
+            return None;
+        }
+
+        let first = U256::from_big_endian(&preimage[..32]);
+        let second = U256::from_big_endian(&preimage[32..]);
+        if first == key {
+            Some((second, MappingLayout::Solidity, offset))
+        } else if second == key {
+            Some((first, MappingLayout::Vyper, offset))
+        } else {
+            None
+        }
+    })
+}
+
+// Traces a view call on `token`, returns the slot that controls its return value and the
+// preimage of every hash computed during the call
+fn find_location(
+    token: Address,
+    data: Bytes,
+    fork_factory: &ForkFactory,
+    block: U64,
+) -> Option<(U256, HashMap<U256, Vec<u8>>)> {
+    let mut tracer = SloadTracer {
+        target: token.0.into(),
+        slots: Vec::new(),
+        preimages: HashMap::new(),
+    };
+    call(
+        token,
+        data.clone(),
+        fork_factory.new_sandbox_fork(),
+        block,
+        &mut tracer,
+    )?;
+
+    let mut candidates = tracer.slots;
+    candidates.sort();
+    candidates.dedup();
+
+    // a slot is only the right one if writing to it changes what the call returns
+    let marker = rU256::from(133713371337u64);
+    let location = candidates.into_iter().find_map(|slot| {
+        let mut sand_box = fork_factory.new_sandbox_fork();
+        sand_box
+            .insert_account_storage(token.0.into(), slot, marker)
+            .ok()?;
+
+        let output = call(token, data.clone(), sand_box, block, &mut NoTracer)?;
+        if output.len() == 32 && U256::from_big_endian(&output) == U256::from(marker) {
+            Some(U256::from(slot))
+        } else {
+            None
+        }
+    })?;
+
+    Some((location, tracer.preimages))
+}
+
+fn call<I: Inspector<ForkDB>>(
+    to: Address,
+    data: Bytes,
+    sand_box_db: ForkDB,
+    block: U64,
+    inspector: &mut I,
+) -> Option<Bytes> {
+    let mut evm: revm::EVM<ForkDB> = revm::EVM::new();
+    evm.database(sand_box_db);
+
+    evm.env.block.number = rU256::from(block.as_u64());
+    evm.env.tx.caller = rAddress::from_str("0x0000000000000000000000000000000000051075").unwrap();
+    evm.env.tx.transact_to = TransactTo::Call(to.0.into());
+    evm.env.tx.data = data.0;
+    evm.env.tx.gas_limit = 1000000;
+    evm.env.tx.gas_price = rU256::ZERO;
+    evm.env.tx.value = rU256::ZERO;
+
+    let result = match evm.inspect(inspector) {
+        Ok(result) => result.result,
+        Err(_) => return None,
+    };
+
+    match result {
+        ExecutionResult::Success {
+            output: Output::Call(o),
+            ..
+        } => Some(o.into()),
+        _ => None,
+    }
+}
+
+/// Records every slot read by `target`, including reads through `delegatecall` (proxies), and
+/// what every KECCAK256 hashed
+struct SloadTracer {
+    target: B160,
+    slots: Vec<rU256>,
+    // hash -> preimage
+    preimages: HashMap<U256, Vec<u8>>,
+}
+
+impl<DB: Database> Inspector<DB> for SloadTracer {
+    fn step(
+        &mut self,
+        interp: &mut Interpreter,
+        _data: &mut EVMData<'_, DB>,
+        _is_static: bool,
+    ) -> InstructionResult {
+        if interp.current_opcode() == opcode::SLOAD && interp.contract.address == self.target {
+            if let Ok(slot) = interp.stack.peek(0) {
+                self.slots.push(slot);
+            }
+        }
+
+        // mapping locations are hashed from memory, before the opcode expands it
+        if interp.current_opcode() == KECCAK256 {
+            if let (Ok(offset), Ok(size)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                let preimage = match (to_usize(offset), to_usize(size)) {
+                    (Some(offset), Some(size)) => interp
+                        .memory
+                        .data()
+                        .get(offset..offset.saturating_add(size)),
+                    _ => None,
+                };
+                if let Some(preimage) = preimage {
+                    self.preimages
+                        .insert(U256::from(keccak256(preimage)), preimage.to_vec());
+                }
+            }
+        }
+
+        InstructionResult::Continue
+    }
+}
+
+fn to_usize(value: rU256) -> Option<usize> {
+    let limbs = value.as_limbs();
+    if limbs[1..].iter().any(|limb| *limb != 0) {
+        return None;
+    }
+    usize::try_from(limbs[0]).ok()
+}
+
+struct NoTracer;
+
+impl<DB: Database> Inspector<DB> for NoTracer {}
+
+fn hash_key(slot: U256, key: Address, layout: MappingLayout) -> U256 {
+    let tokens = match layout {
+        MappingLayout::Solidity => [abi::Token::Address(key), abi::Token::Uint(slot)],
+        MappingLayout::Vyper => [abi::Token::Uint(slot), abi::Token::Address(key)],
+    };
+
+    U256::from(keccak256(abi::encode(&tokens)))
+}
+
+fn erc20_contract() -> BaseContract {
+    BaseContract::from(
+        parse_abi(&[
+            "function balanceOf(address) external view returns (uint256)",
+            "function allowance(address,address) external view returns (uint256)",
+        ])
+        .unwrap(),
+    )
+}
+
+// Addresses that shouldn't have a balance or allowance of their own
+fn probe_owner() -> Address {
+    Address::from_str("0x00000000000000000000000000000000000051A7").unwrap()
+}
+
+fn probe_spender() -> Address {
+    Address::from_str("0x00000000000000000000000000000000000051A8").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // preimages of the hashes a token computes to read `mapping[key]`
+    fn preimages(slot: U256, key: Address, layout: MappingLayout) -> HashMap<U256, Vec<u8>> {
+        let tokens = match layout {
+            MappingLayout::Solidity => [abi::Token::Address(key), abi::Token::Uint(slot)],
+            MappingLayout::Vyper => [abi::Token::Uint(slot), abi::Token::Address(key)],
+        };
+        let preimage = abi::encode(&tokens);
+        HashMap::from([(U256::from(keccak256(&preimage)), preimage)])
+    }
+
+    #[test]
+    fn namespaced_struct_balance_is_matched() {
+        // ERC-7201 base of "openzeppelin.storage.ERC20"
+        let namespace: U256 = "0x52c63247e1f47db19d5ce0460030c497f067ca4cebf71ba98eeadabe20bace00"
+            .parse()
+            .unwrap();
+        let owner = probe_owner();
+        let hashes = preimages(namespace, owner, MappingLayout::Solidity);
+        // second member of the struct the entry holds
+        let location = hash_key(namespace, owner, MappingLayout::Solidity) + 1;
+
+        let (slot, layout, offset) =
+            match_entry(location, owner, &hashes, MAX_STRUCT_OFFSET).unwrap();
+        assert_eq!(
+            (slot, layout, offset),
+            (namespace, MappingLayout::Solidity, 1.into())
+        );
+
+        let mapping = MappingSlot {
+            slot,
+            layout,
+            offset,
+        };
+        assert_eq!(mapping.location(owner), location);
+        // another key isn't taken for the owner
+        assert!(match_entry(location, probe_spender(), &hashes, MAX_STRUCT_OFFSET).is_none());
+    }
+
+    #[test]
+    fn vyper_entry_is_matched() {
+        let owner = probe_owner();
+        let hashes = preimages(U256::from(3), owner, MappingLayout::Vyper);
+        let location = hash_key(U256::from(3), owner, MappingLayout::Vyper);
+
+        assert_eq!(
+            match_entry(location, owner, &hashes, 1),
+            Some((U256::from(3), MappingLayout::Vyper, U256::zero()))
+        );
+    }
+}
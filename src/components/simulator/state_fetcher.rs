@@ -0,0 +1,68 @@
+use ethers::prelude::*;
+use futures::future::BoxFuture;
+
+/// Where the backend fetches the state missing from its cache
+///
+/// Any `Middleware` is one (Ws, Http, Ipc providers), `FixtureState` serves state from memory
+pub trait StateFetcher: Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Balance, nonce and code of `address` at `block`
+    fn basic(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<(U256, U256, Bytes), Self::Error>>;
+
+    /// Value of `slot` in the storage of `address` at `block`
+    fn storage(
+        &self,
+        address: Address,
+        slot: H256,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<H256, Self::Error>>;
+
+    /// Hash of block `number`, `None` if there is no such block
+    fn block_hash(&self, number: u64) -> BoxFuture<'_, Result<Option<H256>, Self::Error>>;
+}
+
+impl<M> StateFetcher for M
+where
+    M: Middleware + 'static,
+    M::Error: 'static,
+{
+    type Error = M::Error;
+
+    fn basic(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<(U256, U256, Bytes), Self::Error>> {
+        Box::pin(async move {
+            let balance = self.get_balance(address, block);
+            let nonce = self.get_transaction_count(address, block);
+            let code = self.get_code(address, block);
+            tokio::try_join!(balance, nonce, code)
+        })
+    }
+
+    fn storage(
+        &self,
+        address: Address,
+        slot: H256,
+        block: Option<BlockId>,
+    ) -> BoxFuture<'_, Result<H256, Self::Error>> {
+        Box::pin(self.get_storage_at(address, slot, block))
+    }
+
+    fn block_hash(&self, number: u64) -> BoxFuture<'_, Result<Option<H256>, Self::Error>> {
+        Box::pin(async move {
+            let block = self.get_block(number).await?;
+            Ok(block.map(|block| {
+                block
+                    .hash
+                    .expect("empty block hash on mined block, this should never happen")
+            }))
+        })
+    }
+}
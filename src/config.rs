@@ -1,3 +1,4 @@
+use crate::contract_modules::uniswap_v2::pool_filter::PoolFilter;
 use ethers::prelude::k256::ecdsa::SigningKey;
 use ethers::prelude::*;
 use ethers::providers::Provider;
@@ -11,6 +12,40 @@ pub struct Config {
     pub wss: Arc<Provider<Ws>>,
     // pub ipc: Arc<Provider<Ipc>>,
     pub wallet: Arc<Wallet<SigningKey>>,
+    // Which stored pools get watched
+    pub pool_filter: PoolFilter,
+    // Client behind the providers, decides how pending txs are traced
+    pub node_type: NodeType,
+}
+
+/// Execution client the providers point to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    Geth,
+    Erigon,
+    Reth,
+    Nethermind,
+}
+
+impl NodeType {
+    /// From `NODE_TYPE`, geth when unset
+    pub fn from_env() -> Self {
+        match std::env::var("NODE_TYPE") {
+            Ok(value) => match value.to_lowercase().as_str() {
+                "geth" => NodeType::Geth,
+                "erigon" => NodeType::Erigon,
+                "reth" => NodeType::Reth,
+                "nethermind" => NodeType::Nethermind,
+                _ => panic!("invalid NODE_TYPE"),
+            },
+            Err(_) => NodeType::Geth,
+        }
+    }
+
+    /// Whether the node serves `trace_callMany` state diffs, geth only has `debug_traceCall`
+    pub fn has_state_diffs(&self) -> bool {
+        *self != NodeType::Geth
+    }
 }
 
 impl Config {
@@ -35,6 +70,8 @@ impl Config {
             http: middleware,
             wss: Arc::new(ws_provider),
             wallet: Arc::new(wallet),
+            pool_filter: PoolFilter::from_env(),
+            node_type: NodeType::from_env(),
         }
     }
 }
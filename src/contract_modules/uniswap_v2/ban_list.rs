@@ -0,0 +1,236 @@
+use ethers::prelude::*;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use super::types::UniV2Pool;
+use crate::helpers::address;
+use crate::state::State;
+
+pub const BAN_LIST_PATH: &str = "./bans.json";
+
+// How often the file is checked for edits while running
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+// Addresses banned before the list existed, the list starts out with these
+const LEGACY_BANS: [&str; 4] = [
+    "0xd46ba6d942050d489dbd938a2c909a5d5039a161",
+    "0x83B04AF7a77C727273B7a582D6Fda65472FCB3f2",
+    "0x9766d2e3f04AE13e8c2EB018eA51dC640d3f9f1F",
+    "0x7E3d39398C9574e1B4f9510Fd37aa3a47d602cDD",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: String,
+    // unix time
+    pub banned_at: u64,
+}
+
+/// Tokens and pools that are never traded, kept in `bans.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BanList {
+    pub tokens: HashMap<Address, Ban>,
+    pub pools: HashMap<Address, Ban>,
+}
+
+impl BanList {
+    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
+        let mut file = File::create(file_path)?;
+        let serialized = serde_json::to_string_pretty(self)?;
+        file.write_all(serialized.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_from_file(file_path: &str) -> std::io::Result<BanList> {
+        let file = File::open(file_path)?;
+        let reader = std::io::BufReader::new(file);
+        let ban_list: BanList = serde_json::from_reader(reader)?;
+        Ok(ban_list)
+    }
+
+    /// The stored list, or the legacy bans when there is none yet
+    pub fn load() -> std::io::Result<BanList> {
+        Self::load_from_file_or_legacy(BAN_LIST_PATH)
+    }
+
+    // A file that can't be read or parsed is an error, falling back would drop its bans
+    fn load_from_file_or_legacy(file_path: &str) -> std::io::Result<BanList> {
+        match Self::load_from_file(file_path) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let mut ban_list = BanList::default();
+                for banned in LEGACY_BANS {
+                    ban_list.ban_token(address(banned), "legacy ban".to_string());
+                }
+                Ok(ban_list)
+            }
+            result => result,
+        }
+    }
+
+    pub fn ban_token(&mut self, token: Address, reason: String) {
+        self.tokens.insert(token, new_ban(reason));
+    }
+
+    pub fn ban_pool(&mut self, pool: Address, reason: String) {
+        self.pools.insert(pool, new_ban(reason));
+    }
+
+    // Removes `banned` from both lists, returns whether it was on one
+    pub fn unban(&mut self, banned: Address) -> bool {
+        let token = self.tokens.remove(&banned).is_some();
+        let pool = self.pools.remove(&banned).is_some();
+        token || pool
+    }
+
+    pub fn is_banned(&self, pool: &UniV2Pool) -> bool {
+        // an address is matched against both lists, the legacy bans don't say what they are
+        [pool.address, pool.token0, pool.token1]
+            .iter()
+            .any(|banned| self.tokens.contains_key(banned) || self.pools.contains_key(banned))
+    }
+}
+
+/// Reloads `bans.json` whenever it changes and drops the cycles of newly banned pools
+///
+/// Lifting a ban only takes effect on the next start, as cycles aren't searched again
+pub async fn watch_ban_list(state: Arc<Mutex<State>>) {
+    let mut last_modified = modified_at(BAN_LIST_PATH);
+
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+
+        let modified = modified_at(BAN_LIST_PATH);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        let ban_list = match BanList::load() {
+            Ok(d) => d,
+            Err(error) => {
+                warn!(
+                    "Failed on reloading ban list, keeping the previous one: {}",
+                    error
+                );
+                continue;
+            }
+        };
+        let removed = state.lock().await.apply_ban_list(&ban_list);
+        info!("Ban list reloaded | {} cycles removed", removed);
+    }
+}
+
+/// Handles `ban token|pool <address> <reason>`, `unban <address>` and `bans`
+/// Returns false when none of them was given
+pub fn run_ban_list_command(args: &[String]) -> bool {
+    // saving over a file that doesn't parse would lose its bans
+    let mut ban_list = match BanList::load() {
+        Ok(d) => d,
+        Err(error) => {
+            error!(
+                "Failed on loading ban list, fix {} first: {}",
+                BAN_LIST_PATH, error
+            );
+            return true;
+        }
+    };
+
+    match args {
+        [command, kind, banned, reason @ ..] if command == "ban" => {
+            let banned = match banned.parse::<Address>() {
+                Ok(d) => d,
+                Err(_) => {
+                    error!("Invalid address: {}", banned);
+                    return true;
+                }
+            };
+            let reason = reason.join(" ");
+
+            match kind.as_str() {
+                "token" => ban_list.ban_token(banned, reason),
+                "pool" => ban_list.ban_pool(banned, reason),
+                _ => {
+                    error!("Usage: ban token|pool <address> <reason>");
+                    return true;
+                }
+            }
+        }
+        [command, banned] if command == "unban" => {
+            let unbanned = banned
+                .parse::<Address>()
+                .map(|banned| ban_list.unban(banned))
+                .unwrap_or(false);
+            if !unbanned {
+                error!("{} is not banned", banned);
+                return true;
+            }
+        }
+        [command] if command == "bans" => {
+            for (kind, bans) in [("token", &ban_list.tokens), ("pool", &ban_list.pools)] {
+                for (banned, ban) in bans {
+                    info!("{} {:?} at {}: {}", kind, banned, ban.banned_at, ban.reason);
+                }
+            }
+            return true;
+        }
+        _ => return false,
+    }
+
+    match ban_list.save_to_file(BAN_LIST_PATH) {
+        Ok(()) => info!("Ban list saved"),
+        Err(error) => error!("Failed on saving ban list: {}", error),
+    }
+    true
+}
+
+fn new_ban(reason: String) -> Ban {
+    let banned_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    Ban { reason, banned_at }
+}
+
+fn modified_at(file_path: &str) -> Option<SystemTime> {
+    std::fs::metadata(file_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("arb_bot_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn missing_file_starts_with_the_legacy_bans() {
+        let ban_list = BanList::load_from_file_or_legacy(&temp_path("missing.json")).unwrap();
+
+        assert_eq!(ban_list.tokens.len(), LEGACY_BANS.len());
+        assert!(ban_list.pools.is_empty());
+    }
+
+    #[test]
+    fn invalid_file_is_an_error() {
+        let path = temp_path("invalid.json");
+        std::fs::write(&path, "{\"tokens\": ").unwrap();
+
+        let loaded = BanList::load_from_file_or_legacy(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+}
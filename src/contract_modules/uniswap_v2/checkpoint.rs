@@ -1,19 +1,102 @@
-use ethers::types::U256;
+use ethers::types::{Address, U256};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{prelude::*, BufReader, BufWriter, Error, ErrorKind};
 
-use super::types::UniV2Pool;
+use super::types::{TokenSafety, TokenTax, UniV2Pool};
+
+pub const JSON_CHECKPOINT_PATH: &str = "./db.json";
+pub const BINARY_CHECKPOINT_PATH: &str = "./db.bin";
+
+// Binary checkpoint layout
+//
+// header: "UV2C" | version (u16 le)
+// record: tag (u8) | payload length (u32 le) | payload | fnv-1a 64 of everything before (u64 le)
+//
+// A snapshot record is followed by its factories and pools, reserve records appended afterwards override the
+// reserves of the snapshot block by block. A crash while appending may leave the last record cut short or with
+// a bad checksum, it's dropped and everything before it is kept
+const MAGIC: [u8; 4] = *b"UV2C";
+// Bump whenever the encoding of a record (or of `UniV2Pool`) changes
+pub const CHECKPOINT_VERSION: u16 = 3;
+
+const TAG_SNAPSHOT: u8 = 1;
+const TAG_POOL: u8 = 2;
+const TAG_RESERVES: u8 = 3;
+const TAG_FACTORY: u8 = 4;
+const TAG_SKIPPED: u8 = 5;
+// Largest payload read, a reserve record of 100k pools is under 9 MiB
+const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Storage {
     pub pools: Vec<UniV2Pool>,
     pub block: U256,
+    // factory -> `allPairsLength` the pools were collected up to
+    #[serde(default)]
+    pub factory_lengths: HashMap<Address, u64>,
+    // pairs left out of `pools` when collected (low reserves, failed tax check), retried on start
+    #[serde(default)]
+    pub skipped: Vec<UniV2Pool>,
+}
+
+/// New reserves of a pool after a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveDelta {
+    pub address: Address,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+/// Single entry of a binary checkpoint
+#[derive(Debug, Clone)]
+pub enum CheckpointRecord {
+    // Start of a full snapshot, followed by `pools` pool records
+    Snapshot {
+        block: U256,
+        pools: u32,
+    },
+    Pool(UniV2Pool),
+    Skipped(UniV2Pool),
+    Factory {
+        factory: Address,
+        pairs_length: u64,
+    },
+    Reserves {
+        block: U256,
+        deltas: Vec<ReserveDelta>,
+    },
 }
 
 impl Storage {
     pub fn new(pools: Vec<UniV2Pool>, block: U256) -> Self {
-        Self { pools, block }
+        Self {
+            pools,
+            block,
+            factory_lengths: HashMap::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Adds `pools`, replacing the stored ones with the same address
+    pub fn merge_pools(&mut self, pools: Vec<UniV2Pool>) {
+        let mut indexes: HashMap<Address, usize> = self
+            .pools
+            .iter()
+            .enumerate()
+            .map(|(index, pool)| (pool.address, index))
+            .collect();
+
+        for pool in pools {
+            match indexes.get(&pool.address) {
+                Some(index) => self.pools[*index] = pool,
+                None => {
+                    indexes.insert(pool.address, self.pools.len());
+                    self.pools.push(pool);
+                }
+            }
+        }
     }
 
     pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
@@ -29,4 +112,597 @@ impl Storage {
         let storage: Storage = serde_json::from_reader(reader)?;
         Ok(storage)
     }
+
+    /// `save_to_binary` to a file next to `file_path`, then moved over it, so that a write cut
+    /// short (eg: on exit) leaves the previous checkpoint in place
+    pub fn replace_binary(&self, file_path: &str) -> std::io::Result<()> {
+        let tmp_path = format!("{}.tmp", file_path);
+        self.save_to_binary(&tmp_path)?;
+        std::fs::rename(tmp_path, file_path)
+    }
+
+    /// Writes a fresh binary checkpoint, dropping any reserve records appended before
+    pub fn save_to_binary(&self, file_path: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+
+        let mut payload = Vec::new();
+        write_u256(&mut payload, self.block);
+        payload.extend((self.pools.len() as u32).to_le_bytes());
+        write_record(&mut writer, TAG_SNAPSHOT, &payload)?;
+
+        for (factory, pairs_length) in &self.factory_lengths {
+            payload.clear();
+            payload.extend(factory.as_bytes());
+            payload.extend(pairs_length.to_le_bytes());
+            write_record(&mut writer, TAG_FACTORY, &payload)?;
+        }
+
+        for pool in &self.pools {
+            payload.clear();
+            write_pool(&mut payload, pool);
+            write_record(&mut writer, TAG_POOL, &payload)?;
+        }
+
+        for pool in &self.skipped {
+            payload.clear();
+            write_pool(&mut payload, pool);
+            write_record(&mut writer, TAG_SKIPPED, &payload)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Loads a binary checkpoint and applies the reserve records appended to it
+    ///
+    /// A torn record at the end is cut off the file, so that the next appends can be read
+    pub fn load_from_binary(file_path: &str) -> std::io::Result<Storage> {
+        let mut storage = Storage::new(Vec::new(), U256::zero());
+        // pool address -> index in `pools`
+        let mut indexes: HashMap<Address, usize> = HashMap::new();
+
+        let mut records = Self::stream_from_binary(file_path)?;
+        for record in &mut records {
+            match record? {
+                CheckpointRecord::Snapshot { block, pools } => {
+                    storage.block = block;
+                    storage.pools = Vec::with_capacity(pools as usize);
+                    storage.factory_lengths.clear();
+                    storage.skipped.clear();
+                    indexes.clear();
+                }
+                CheckpointRecord::Factory {
+                    factory,
+                    pairs_length,
+                } => {
+                    storage.factory_lengths.insert(factory, pairs_length);
+                }
+                CheckpointRecord::Pool(pool) => {
+                    indexes.insert(pool.address, storage.pools.len());
+                    storage.pools.push(pool);
+                }
+                CheckpointRecord::Skipped(pool) => storage.skipped.push(pool),
+                CheckpointRecord::Reserves { block, deltas } => {
+                    for delta in deltas {
+                        if let Some(index) = indexes.get(&delta.address) {
+                            storage.pools[*index].reserve0 = delta.reserve0;
+                            storage.pools[*index].reserve1 = delta.reserve1;
+                        }
+                    }
+                    storage.block = block;
+                }
+            }
+        }
+
+        if records.is_torn() {
+            OpenOptions::new()
+                .write(true)
+                .open(file_path)?
+                .set_len(records.valid_length())?;
+        }
+
+        Ok(storage)
+    }
+
+    /// Reads a binary checkpoint one record at a time, without loading the whole file
+    pub fn stream_from_binary(
+        file_path: &str,
+    ) -> std::io::Result<CheckpointReader<BufReader<File>>> {
+        CheckpointReader::new(BufReader::new(File::open(file_path)?))
+    }
+
+    /// Adds the reserves changed in `block` to the end of an existing binary checkpoint
+    pub fn append_reserves(
+        file_path: &str,
+        block: U256,
+        deltas: &[ReserveDelta],
+    ) -> std::io::Result<()> {
+        let mut payload = Vec::new();
+        write_u256(&mut payload, block);
+        payload.extend((deltas.len() as u32).to_le_bytes());
+        for delta in deltas {
+            payload.extend(delta.address.as_bytes());
+            write_u256(&mut payload, delta.reserve0);
+            write_u256(&mut payload, delta.reserve1);
+        }
+
+        let mut writer = BufWriter::new(OpenOptions::new().append(true).open(file_path)?);
+        write_record(&mut writer, TAG_RESERVES, &payload)?;
+        writer.flush()
+    }
+
+    /// Loads the binary checkpoint if there is one, the JSON one otherwise
+    pub fn load() -> std::io::Result<Storage> {
+        match Self::load_from_binary(BINARY_CHECKPOINT_PATH) {
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                Self::load_from_file(JSON_CHECKPOINT_PATH)
+            }
+            result => result,
+        }
+    }
+
+    pub fn convert_json_to_binary(json_path: &str, binary_path: &str) -> std::io::Result<()> {
+        Self::load_from_file(json_path)?.save_to_binary(binary_path)
+    }
+
+    pub fn convert_binary_to_json(binary_path: &str, json_path: &str) -> std::io::Result<()> {
+        Self::load_from_binary(binary_path)?.save_to_file(json_path)
+    }
+}
+
+/// Iterates over the records of a binary checkpoint, checking each one against its checksum
+pub struct CheckpointReader<R: Read> {
+    reader: R,
+    // read ahead to tell the last record from the others
+    peeked: Option<u8>,
+    // bytes read so far, and up to the end of the last valid record
+    offset: u64,
+    valid_length: u64,
+    torn: bool,
+}
+
+impl<R: Read> CheckpointReader<R> {
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid_data("not a binary checkpoint"));
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid_data(&format!(
+                "checkpoint version {} is not supported (expected {})",
+                version, CHECKPOINT_VERSION
+            )));
+        }
+
+        Ok(Self {
+            reader,
+            peeked: None,
+            offset: header.len() as u64,
+            valid_length: header.len() as u64,
+            torn: false,
+        })
+    }
+
+    /// Whether the last record was cut short or corrupt and was dropped
+    pub fn is_torn(&self) -> bool {
+        self.torn
+    }
+
+    /// Bytes up to the end of the last valid record read
+    pub fn valid_length(&self) -> u64 {
+        self.valid_length
+    }
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> std::io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if let Some(byte) = self.peeked.take() {
+            buf[0] = byte;
+            buf = &mut buf[1..];
+        }
+        self.reader.read_exact(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    // Whether the reader is at the end of the file
+    fn at_end(&mut self) -> std::io::Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(false);
+        }
+
+        let mut byte = [0u8; 1];
+        match self.reader.read_exact(&mut byte) {
+            Ok(()) => {
+                self.offset += 1;
+                self.peeked = Some(byte[0]);
+                Ok(false)
+            }
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+
+    // Payload of the record starting with `head[0]`, `None` if it's cut short
+    fn read_payload(&mut self, head: &mut [u8; 5]) -> std::io::Result<Option<Vec<u8>>> {
+        let (payload, checksum) = match self.read_payload_and_checksum(head) {
+            Ok(d) => d,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        if checksum != fnv1a(&[&head[..], &payload]) {
+            // only the last record can be half written
+            if self.at_end()? {
+                return Ok(None);
+            }
+            return Err(invalid_data("checkpoint record checksum mismatch"));
+        }
+
+        Ok(Some(payload))
+    }
+
+    fn read_payload_and_checksum(&mut self, head: &mut [u8; 5]) -> std::io::Result<(Vec<u8>, u64)> {
+        self.read_exact(&mut head[1..])?;
+
+        let length = u32::from_le_bytes([head[1], head[2], head[3], head[4]]) as usize;
+        if length > MAX_RECORD_LENGTH {
+            return Err(invalid_data("checkpoint record is over the size limit"));
+        }
+        let mut payload = vec![0u8; length];
+        self.read_exact(&mut payload)?;
+
+        let mut checksum = [0u8; 8];
+        self.read_exact(&mut checksum)?;
+        Ok((payload, u64::from_le_bytes(checksum)))
+    }
+
+    fn read_record(&mut self) -> std::io::Result<Option<CheckpointRecord>> {
+        if self.torn {
+            return Ok(None);
+        }
+
+        let mut head = [0u8; 5];
+        match self.read_exact(&mut head[..1]) {
+            Ok(()) => {}
+            // clean end of file, between two records
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+
+        let payload = match self.read_payload(&mut head)? {
+            Some(d) => d,
+            None => {
+                self.torn = true;
+                return Ok(None);
+            }
+        };
+        self.valid_length = self.offset;
+
+        let mut cursor = Cursor(&payload);
+        let record = match head[0] {
+            TAG_SNAPSHOT => CheckpointRecord::Snapshot {
+                block: cursor.u256()?,
+                pools: cursor.u32()?,
+            },
+            TAG_POOL => CheckpointRecord::Pool(read_pool(&mut cursor)?),
+            TAG_SKIPPED => CheckpointRecord::Skipped(read_pool(&mut cursor)?),
+            TAG_FACTORY => CheckpointRecord::Factory {
+                factory: cursor.address()?,
+                pairs_length: cursor.u64()?,
+            },
+            TAG_RESERVES => {
+                let block = cursor.u256()?;
+                let count = cursor.u32()?;
+                let mut deltas = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    deltas.push(ReserveDelta {
+                        address: cursor.address()?,
+                        reserve0: cursor.u256()?,
+                        reserve1: cursor.u256()?,
+                    });
+                }
+                CheckpointRecord::Reserves { block, deltas }
+            }
+            tag => return Err(invalid_data(&format!("unknown checkpoint record {}", tag))),
+        };
+
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Iterator for CheckpointReader<R> {
+    type Item = std::io::Result<CheckpointRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, tag: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut head = [tag, 0, 0, 0, 0];
+    head[1..].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+
+    writer.write_all(&head)?;
+    writer.write_all(payload)?;
+    writer.write_all(&fnv1a(&[&head, payload]).to_le_bytes())
+}
+
+fn write_pool(out: &mut Vec<u8>, pool: &UniV2Pool) {
+    for address in [pool.address, pool.factory, pool.token0, pool.token1] {
+        out.extend(address.as_bytes());
+    }
+    for value in [pool.reserve0, pool.reserve1, pool.router_fee] {
+        write_u256(out, value);
+    }
+    for tax in [pool.fees0, pool.fees1] {
+        write_u256(out, tax.buy);
+        write_u256(out, tax.sell);
+        write_u256(out, tax.transfer);
+        out.extend(tax.verified_at.to_le_bytes());
+        out.push(tax.changed as u8);
+    }
+    out.push(safety_to_byte(pool.safety0));
+    out.push(safety_to_byte(pool.safety1));
+}
+
+fn read_pool(cursor: &mut Cursor) -> std::io::Result<UniV2Pool> {
+    Ok(UniV2Pool {
+        address: cursor.address()?,
+        factory: cursor.address()?,
+        token0: cursor.address()?,
+        token1: cursor.address()?,
+        reserve0: cursor.u256()?,
+        reserve1: cursor.u256()?,
+        router_fee: cursor.u256()?,
+        fees0: read_tax(cursor)?,
+        fees1: read_tax(cursor)?,
+        safety0: safety_from_byte(cursor.u8()?)?,
+        safety1: safety_from_byte(cursor.u8()?)?,
+    })
+}
+
+fn read_tax(cursor: &mut Cursor) -> std::io::Result<TokenTax> {
+    Ok(TokenTax {
+        buy: cursor.u256()?,
+        sell: cursor.u256()?,
+        transfer: cursor.u256()?,
+        verified_at: cursor.u64()?,
+        changed: cursor.u8()? != 0,
+    })
+}
+
+fn safety_to_byte(safety: TokenSafety) -> u8 {
+    match safety {
+        TokenSafety::Safe => 0,
+        TokenSafety::Taxed => 1,
+        TokenSafety::Limited => 2,
+        TokenSafety::Honeypot => 3,
+        TokenSafety::Unchecked => 4,
+    }
+}
+
+fn safety_from_byte(byte: u8) -> std::io::Result<TokenSafety> {
+    match byte {
+        0 => Ok(TokenSafety::Safe),
+        1 => Ok(TokenSafety::Taxed),
+        2 => Ok(TokenSafety::Limited),
+        3 => Ok(TokenSafety::Honeypot),
+        4 => Ok(TokenSafety::Unchecked),
+        _ => Err(invalid_data("unknown token safety")),
+    }
+}
+
+// U256 as its length in bytes followed by its big endian bytes, most values are far from 32 bytes
+fn write_u256(out: &mut Vec<u8>, value: U256) {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let length = 32 - value.leading_zeros() as usize / 8;
+
+    out.push(length as u8);
+    out.extend(&bytes[32 - length..]);
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, length: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < length {
+            return Err(invalid_data("checkpoint record is too short"));
+        }
+
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn u256(&mut self) -> std::io::Result<U256> {
+        let length = self.u8()? as usize;
+        if length > 32 {
+            return Err(invalid_data("checkpoint value is over 32 bytes"));
+        }
+
+        Ok(U256::from_big_endian(self.take(length)?))
+    }
+
+    fn address(&mut self) -> std::io::Result<Address> {
+        Ok(Address::from_slice(self.take(20)?))
+    }
+}
+
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn pool(address: u64) -> UniV2Pool {
+        let [token0, token1] = [1, 2].map(Address::from_low_u64_be);
+        test_utils::pool(address, token0, token1, U256::from(1000), U256::from(2000))
+    }
+
+    fn delta(reserve0: u64) -> ReserveDelta {
+        ReserveDelta {
+            address: Address::from_low_u64_be(10),
+            reserve0: U256::from(reserve0),
+            reserve1: U256::from(2000),
+        }
+    }
+
+    // a checkpoint at block 1 with the reserves of block 2 appended
+    fn checkpoint(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.bin", name, std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        Storage::new(vec![pool(10)], U256::one())
+            .save_to_binary(&path)
+            .unwrap();
+        Storage::append_reserves(&path, U256::from(2), &[delta(1100)]).unwrap();
+        path
+    }
+
+    #[test]
+    fn torn_append_is_dropped_and_cut_off() {
+        let path = checkpoint("torn-append");
+        let valid_length = std::fs::metadata(&path).unwrap().len();
+
+        // crashed halfway through appending block 3
+        Storage::append_reserves(&path, U256::from(3), &[delta(1200)]).unwrap();
+        let full_length = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(full_length - 10)
+            .unwrap();
+
+        let storage = Storage::load_from_binary(&path).unwrap();
+        assert_eq!(storage.block, U256::from(2));
+        assert_eq!(storage.pools[0].reserve0, U256::from(1100));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_length);
+
+        // appends after the cut are read again
+        Storage::append_reserves(&path, U256::from(3), &[delta(1300)]).unwrap();
+        let storage = Storage::load_from_binary(&path).unwrap();
+        assert_eq!(storage.pools[0].reserve0, U256::from(1300));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_before_the_end_fails_the_load() {
+        let path = checkpoint("corrupt-record");
+        let mut bytes = std::fs::read(&path).unwrap();
+        // last checksum byte of the last pool record, the reserve record follows
+        let reserve_record = bytes.len() - (5 + 1 + 1 + 4 + 20 + 2 * 3 + 8);
+        bytes[reserve_record - 1] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Storage::load_from_binary(&path).is_err());
+
+        // the same damage on the last record only drops it
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[reserve_record - 1] ^= 1;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(Storage::load_from_binary(&path).unwrap().block, U256::one());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skipped_pools_are_kept_apart() {
+        let path = checkpoint("skipped");
+
+        let mut storage = Storage::load_from_binary(&path).unwrap();
+        storage.skipped.push(pool(11));
+        storage.save_to_binary(&path).unwrap();
+
+        let storage = Storage::load_from_binary(&path).unwrap();
+        assert_eq!(storage.pools.len(), 1);
+        assert_eq!(storage.skipped.len(), 1);
+        assert_eq!(storage.skipped[0].address, Address::from_low_u64_be(11));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn json_checkpoint_with_single_taxes_loads() {
+        let path = std::env::temp_dir().join(format!("single-taxes-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        // as saved before taxes were measured per side
+        let pool = r#"{
+            "address": "0x000000000000000000000000000000000000000a",
+            "token0": "0x0000000000000000000000000000000000000001",
+            "token1": "0x0000000000000000000000000000000000000002",
+            "reserve0": "0x3e8",
+            "reserve1": "0x7d0",
+            "router_fee": "0x26f2",
+            "fees0": "0x0",
+            "fees1": "0x64"
+        }"#;
+        std::fs::write(path, format!(r#"{{"pools": [{}], "block": "0x1"}}"#, pool)).unwrap();
+
+        let storage = Storage::load_from_file(path).unwrap();
+        let pool = &storage.pools[0];
+        assert_eq!(pool.fees0, TokenTax::default());
+        assert_eq!(pool.fees1.sell, U256::from(100));
+        assert!(pool.fees1.buy.is_zero());
+        // checked again before it's traded
+        assert_eq!(pool.safety1, TokenSafety::Unchecked);
+
+        // and saved in the current format
+        storage.save_to_file(path).unwrap();
+        let storage = Storage::load_from_file(path).unwrap();
+        assert_eq!(storage.pools[0].fees1.sell, U256::from(100));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replaced_checkpoint_drops_appended_reserves() {
+        let path = checkpoint("replace");
+
+        Storage::new(vec![pool(10)], U256::from(5))
+            .replace_binary(&path)
+            .unwrap();
+        let storage = Storage::load_from_binary(&path).unwrap();
+        assert_eq!(storage.block, U256::from(5));
+        assert_eq!(storage.pools[0].reserve0, U256::from(1000));
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
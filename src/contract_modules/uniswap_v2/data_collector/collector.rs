@@ -1,34 +1,99 @@
-use crate::{constants::UniV2Factory, contract_modules::uniswap_v2::types::UniV2Pool};
+use crate::{
+    constants::UniV2Factory,
+    contract_modules::uniswap_v2::types::{TokenSafety, TokenTax, UniV2, UniV2Pool},
+};
 use ethers::{
-    abi::{ParamType, Token},
+    abi::{parse_abi, Abi, ParamType, Token},
+    contract::{Contract, Multicall},
     prelude::abigen,
     providers::Middleware,
     types::{Address, Bytes, H160, U256},
 };
+use futures::{stream, StreamExt};
 use indicatif::ProgressBar;
+use log::*;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Duration;
+
+use super::throttle::AsyncRequestThrottle;
 
 abigen!(
     GetUniswapV2PairsBatchRequest,
     "src/abi/BatchCollector.json";
 );
 
+// Pairs per batch to start with, halved whenever a batch hits the codesize or gas limit
+const INITIAL_STEP: u64 = 150;
+// Batches in flight at once
+const MAX_CONCURRENT_BATCHES: usize = 8;
+// Requests per second sent to the node, 0 disables throttling
+const REQUESTS_PER_SECOND: usize = 20;
+// Calls bundled into one multicall, when reading every stored pool
+const MULTICALL_STEP: usize = 500;
+// Retries of a failed batch before its range is reported as missing
+const MAX_RETRIES: u32 = 4;
+// Wait before the first retry, doubled on every next one
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    // The deploy call ran into the codesize or gas limit, a smaller batch may work
+    #[error("Batch too large: {0}")]
+    TooLarge(String),
+    #[error("Request failed: {0}")]
+    Request(String),
+    #[error("Failed to decode batch: {0}")]
+    Decode(String),
+}
+
+/// Pairs of a factory, and the `allPairs` indexes that could not be fetched
+#[derive(Debug, Default)]
+pub struct CollectedPairs {
+    pub pairs: Vec<UniV2Pool>,
+    pub missing: Vec<Range<u64>>,
+    // `allPairsLength` at the time of collection
+    pub pairs_length: u64,
+}
+
+impl CollectedPairs {
+    /// Index to start from next time, so that missing pairs are fetched again
+    pub fn next_index(&self) -> u64 {
+        self.missing
+            .iter()
+            .map(|range| range.start)
+            .min()
+            .unwrap_or(self.pairs_length)
+    }
+}
+
+/// Fetches the pairs at indexes `from..to` of `factory`
 pub async fn get_pairs_batch_request<M: Middleware>(
     factory: H160,
+    fee: U256,
     from: U256,
-    step: U256,
+    to: U256,
     middleware: Arc<M>,
-) -> Vec<UniV2Pool> {
+) -> Result<Vec<UniV2Pool>, BatchError> {
     let mut pairs = vec![];
 
     let constructor_args = Token::Tuple(vec![
         Token::Uint(from),
-        Token::Uint(step),
+        Token::Uint(to),
         Token::Address(factory),
     ]);
 
-    let deployer = GetUniswapV2PairsBatchRequest::deploy(middleware, constructor_args).unwrap();
-    let return_data: Bytes = deployer.call_raw().await.unwrap();
+    let deployer = GetUniswapV2PairsBatchRequest::deploy(middleware, constructor_args)
+        .map_err(|error| BatchError::Request(error.to_string()))?;
+    let return_data: Bytes = deployer.call_raw().await.map_err(|error| {
+        let message = error.to_string();
+        if is_limit_error(&message) {
+            BatchError::TooLarge(message)
+        } else {
+            BatchError::Request(message)
+        }
+    })?;
 
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
@@ -40,25 +105,46 @@ pub async fn get_pairs_batch_request<M: Middleware>(
         ])))],
         &return_data,
     )
-    .unwrap();
+    .map_err(|error| BatchError::Decode(error.to_string()))?;
 
     for tokens in return_data_tokens {
         if let Some(tokens_arr) = tokens.into_array() {
             for tup in tokens_arr {
                 if let Some(pool_data) = tup.into_tuple() {
+                    let decode_error = || BatchError::Decode("unexpected pool data".to_string());
+                    let address = pool_data[0]
+                        .to_owned()
+                        .into_address()
+                        .ok_or_else(decode_error)?;
+
                     //If the pool token A is not zero, signaling that the pool data was populated
-                    if !pool_data[0].to_owned().into_address().unwrap().is_zero() {
+                    if !address.is_zero() {
                         //Update the pool data
                         let pool_internal = UniV2Pool {
-                            address: pool_data[0].to_owned().into_address().unwrap(),
-                            token0: pool_data[1].to_owned().into_address().unwrap(),
-                            token1: pool_data[2].to_owned().into_address().unwrap(),
-                            reserve0: pool_data[3].to_owned().into_uint().unwrap(),
-                            reserve1: pool_data[4].to_owned().into_uint().unwrap(),
-                            router_fee: U256::from(9970),
-
-                            fees0: U256::zero(),
-                            fees1: U256::zero(),
+                            address,
+                            factory,
+                            token0: pool_data[1]
+                                .to_owned()
+                                .into_address()
+                                .ok_or_else(decode_error)?,
+                            token1: pool_data[2]
+                                .to_owned()
+                                .into_address()
+                                .ok_or_else(decode_error)?,
+                            reserve0: pool_data[3]
+                                .to_owned()
+                                .into_uint()
+                                .ok_or_else(decode_error)?,
+                            reserve1: pool_data[4]
+                                .to_owned()
+                                .into_uint()
+                                .ok_or_else(decode_error)?,
+                            router_fee: fee,
+
+                            fees0: TokenTax::default(),
+                            fees1: TokenTax::default(),
+                            safety0: TokenSafety::default(),
+                            safety1: TokenSafety::default(),
                         };
 
                         pairs.push(pool_internal);
@@ -68,44 +154,270 @@ pub async fn get_pairs_batch_request<M: Middleware>(
         }
     }
 
-    pairs
+    Ok(pairs)
 }
 
+/// Fetches the pairs of `dex` from index `from` on
 pub async fn get_all_pairs_via_batched_calls<M: 'static + Middleware>(
-    factory_address: Address,
+    dex: &UniV2,
+    from: u64,
     middleware: Arc<M>,
     progress_bar: ProgressBar,
-) -> Vec<UniV2Pool> {
-    let factory = UniV2Factory::new(factory_address, middleware.clone());
+) -> CollectedPairs {
+    let throttle = AsyncRequestThrottle::new(REQUESTS_PER_SECOND);
+    let factory = UniV2Factory::new(dex.factory, middleware.clone());
 
-    let pairs_length: U256 = factory.all_pairs_length().call().await.unwrap();
+    throttle.increment_or_sleep(1).await;
+    let pairs_length = match factory.all_pairs_length().call().await {
+        Ok(d) => d.as_u64(),
+        Err(error) => {
+            error!("Failed to get pairs length of {:?}: {}", dex.factory, error);
+            // nothing past `from` was fetched
+            return CollectedPairs {
+                pairs_length: from,
+                ..Default::default()
+            };
+        }
+    };
+    let from = from.min(pairs_length);
     //Initialize the progress bar message
-    progress_bar.set_length(pairs_length.as_u64());
+    progress_bar.set_length(pairs_length - from);
 
-    let mut pairs = vec![];
-    let step = 150; //max batch size for this call until codesize is too large
-    let mut idx_from = U256::zero();
-    let mut idx_to = if step > pairs_length.as_usize() {
-        pairs_length
-    } else {
-        U256::from(step)
+    let ranges = (from..pairs_length)
+        .step_by(INITIAL_STEP as usize)
+        .map(|from| from..(from + INITIAL_STEP).min(pairs_length));
+
+    let mut collected = CollectedPairs {
+        pairs_length,
+        ..Default::default()
     };
+    let mut batches = stream::iter(ranges)
+        .map(|range| get_pairs_range(dex, range, middleware.clone(), &throttle, &progress_bar))
+        .buffer_unordered(MAX_CONCURRENT_BATCHES);
 
-    for _ in (0..pairs_length.as_u128()).step_by(step) {
-        pairs.append(
-            &mut get_pairs_batch_request(factory_address, idx_from, idx_to, middleware.clone())
-                .await,
+    while let Some(batch) = batches.next().await {
+        collected.pairs.extend(batch.pairs);
+        collected.missing.extend(batch.missing);
+    }
+
+    collected.missing.sort_by_key(|range| range.start);
+    for range in &collected.missing {
+        warn!(
+            "Missing pairs {}..{} of factory {:?}",
+            range.start, range.end, dex.factory
         );
+    }
 
-        idx_from = idx_to;
+    progress_bar.reset();
+    collected
+}
 
-        if idx_to + step > pairs_length {
-            idx_to = pairs_length - 1
-        } else {
-            idx_to = idx_to + step;
+// Fetches `range`, splitting it while the node rejects it as too large
+async fn get_pairs_range<M: Middleware>(
+    dex: &UniV2,
+    range: Range<u64>,
+    middleware: Arc<M>,
+    throttle: &AsyncRequestThrottle,
+    progress_bar: &ProgressBar,
+) -> CollectedPairs {
+    let mut collected = CollectedPairs::default();
+    let mut pending = vec![range];
+
+    while let Some(range) = pending.pop() {
+        match get_pairs_batch_request_with_retry(dex, range.clone(), middleware.clone(), throttle)
+            .await
+        {
+            Ok(pairs) => {
+                collected.pairs.extend(pairs);
+                progress_bar.inc(range.end - range.start);
+            }
+            Err(BatchError::TooLarge(_)) if range.end - range.start > 1 => {
+                let middle = range.start + (range.end - range.start) / 2;
+                pending.push(middle..range.end);
+                pending.push(range.start..middle);
+            }
+            Err(error) => {
+                debug!(
+                    "Giving up on pairs {}..{}: {}",
+                    range.start, range.end, error
+                );
+                collected.missing.push(range.clone());
+                progress_bar.inc(range.end - range.start);
+            }
         }
-        progress_bar.inc(step as u64);
     }
+
+    collected
+}
+
+async fn get_pairs_batch_request_with_retry<M: Middleware>(
+    dex: &UniV2,
+    range: Range<u64>,
+    middleware: Arc<M>,
+    throttle: &AsyncRequestThrottle,
+) -> Result<Vec<UniV2Pool>, BatchError> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+
+    loop {
+        throttle.increment_or_sleep(1).await;
+        let result = get_pairs_batch_request(
+            dex.factory,
+            dex.fee,
+            U256::from(range.start),
+            U256::from(range.end),
+            middleware.clone(),
+        )
+        .await;
+
+        match result {
+            // retrying the same size won't help
+            Err(BatchError::TooLarge(_)) => return result,
+            Err(_) if attempt < MAX_RETRIES => {
+                attempt += 1;
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            _ => return result,
+        }
+    }
+}
+
+/// Reserves of `pools` at the latest block, through batched `getReserves` calls
+///
+/// Pools whose reserves could not be read are left out
+pub async fn get_reserves_via_batched_calls<M: 'static + Middleware>(
+    pools: &[Address],
+    middleware: Arc<M>,
+    progress_bar: ProgressBar,
+) -> HashMap<Address, (U256, U256)> {
+    let signature = "function getReserves() external view returns (uint112,uint112,uint32)";
+    call_each(pools, signature, "getReserves", middleware, progress_bar)
+        .await
+        .into_iter()
+        .filter_map(|(pool, output)| {
+            let reserves = output.into_tuple()?;
+            let reserve0 = reserves.first()?.clone().into_uint()?;
+            let reserve1 = reserves.get(1)?.clone().into_uint()?;
+            Some((pool, (reserve0, reserve1)))
+        })
+        .collect()
+}
+
+/// Factory of each of `pools`, through batched `factory` calls
+pub async fn get_factories_via_batched_calls<M: 'static + Middleware>(
+    pools: &[Address],
+    middleware: Arc<M>,
+    progress_bar: ProgressBar,
+) -> HashMap<Address, Address> {
+    let signature = "function factory() external view returns (address)";
+    call_each(pools, signature, "factory", middleware, progress_bar)
+        .await
+        .into_iter()
+        .filter_map(|(pool, output)| Some((pool, output.into_address()?)))
+        .collect()
+}
+
+// Calls `name`, taking no arguments, on every one of `targets` through Multicall3
+// Returns the output of every call that went through
+async fn call_each<M: 'static + Middleware>(
+    targets: &[Address],
+    signature: &str,
+    name: &str,
+    middleware: Arc<M>,
+    progress_bar: ProgressBar,
+) -> HashMap<Address, Token> {
+    let multicall = match Multicall::new(middleware.clone(), None).await {
+        Ok(d) => d,
+        Err(error) => {
+            error!("Failed to set up multicall: {}", error);
+            return HashMap::new();
+        }
+    };
+    let abi = parse_abi(&[signature]).unwrap();
+    let throttle = AsyncRequestThrottle::new(REQUESTS_PER_SECOND);
+    progress_bar.set_length(targets.len() as u64);
+
+    let mut batches = stream::iter(targets.chunks(MULTICALL_STEP))
+        .map(|targets| {
+            let calls = multicall_with_retry(
+                targets,
+                &abi,
+                name,
+                multicall.clone(),
+                middleware.clone(),
+                &throttle,
+            );
+            async move { (targets, calls.await) }
+        })
+        .buffer_unordered(MAX_CONCURRENT_BATCHES);
+
+    let mut outputs = HashMap::new();
+    while let Some((targets, batch)) = batches.next().await {
+        progress_bar.inc(targets.len() as u64);
+        match batch {
+            Ok(batch) => outputs.extend(batch),
+            Err(error) => warn!(
+                "Giving up on {} of {} contracts: {}",
+                name,
+                targets.len(),
+                error
+            ),
+        }
+    }
+
     progress_bar.reset();
-    pairs
+    outputs
+}
+
+async fn multicall_with_retry<M: Middleware>(
+    targets: &[Address],
+    abi: &Abi,
+    name: &str,
+    mut multicall: Multicall<M>,
+    middleware: Arc<M>,
+    throttle: &AsyncRequestThrottle,
+) -> Result<Vec<(Address, Token)>, BatchError> {
+    for target in targets {
+        let call = Contract::new(*target, abi.clone(), middleware.clone())
+            .method::<_, Token>(name, ())
+            .map_err(|error| BatchError::Request(error.to_string()))?;
+        // a target without the function fails alone
+        multicall.add_call(call, true);
+    }
+
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+
+    loop {
+        throttle.increment_or_sleep(1).await;
+        match multicall.call_raw().await {
+            Ok(results) => {
+                return Ok(targets
+                    .iter()
+                    .zip(results)
+                    .filter_map(|(target, result)| Some((*target, result.ok()?)))
+                    .collect())
+            }
+            Err(_) if attempt < MAX_RETRIES => {
+                attempt += 1;
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(error) => return Err(BatchError::Request(error.to_string())),
+        }
+    }
+}
+
+fn is_limit_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "code size",
+        "codesize",
+        "out of gas",
+        "gas limit",
+        "gas required exceeds",
+    ]
+    .iter()
+    .any(|limit| message.contains(limit))
 }
@@ -1,31 +1,66 @@
-use super::collector::get_all_pairs_via_batched_calls;
-
+use super::collector::{
+    get_all_pairs_via_batched_calls, get_factories_via_batched_calls,
+    get_reserves_via_batched_calls,
+};
 use crate::components::simulator::fork_factory::ForkFactory;
+use crate::components::simulator::slot_finder::SlotFinder;
+use crate::contract_modules::uniswap_v2::checkpoint::Storage;
+use crate::contract_modules::uniswap_v2::constants::get_weth_address;
 use crate::contract_modules::uniswap_v2::data_collector::tax_checker::{
-    get_tax, inject_tax_checker_code, insert_fake_approval,
+    get_token_tax, inject_tax_checker_code, insert_fake_approval,
+};
+use crate::contract_modules::uniswap_v2::data_collector::token_safety::{
+    fund_senders, get_token_safety,
 };
-use crate::contract_modules::uniswap_v2::types::{UniV2, UniV2Pool};
-use crate::helpers::address;
+use crate::contract_modules::uniswap_v2::types::{TokenSafety, TokenTax, UniV2, UniV2Pool};
 
 use ethers::prelude::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::*;
 use revm::db::{CacheDB, EmptyDB};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+// Reserves on both sides a pair needs to be collected
+const MIN_RESERVE: u64 = 1000000;
+
+/// Outcome of `get_all_pairs`
+pub struct CollectedPools {
+    // pairs to merge into the stored pools
+    pub pairs: Vec<UniV2Pool>,
+    // pairs under the reserve threshold or failing the tax check, to retry on the next start
+    pub skipped: Vec<UniV2Pool>,
+    // factory -> `allPairsLength` to continue from
+    pub factory_lengths: HashMap<Address, u64>,
+}
 
+/// Collects and tax checks the pairs created since `storage` was collected, and retries the
+/// pairs it skipped
+///
+/// Tokens already in `storage` keep their stored taxes, use the tax validator to refresh those.
+/// Stored pools with an unchecked token are returned again once checked. `None` when shut down
 pub async fn get_all_pairs(
     factorys: Vec<UniV2>,
+    storage: &Storage,
     wss_provider: Arc<Provider<Ws>>,
-) -> Option<Vec<UniV2Pool>> {
+    shutdown: &CancellationToken,
+) -> Option<CollectedPools> {
     let multi_progress_bar = MultiProgress::new();
     let current_block = wss_provider.get_block_number().await.unwrap();
     let cache_db: CacheDB<EmptyDB> = CacheDB::new(EmptyDB::default());
-    let mut fork_factory = ForkFactory::new_sandbox_factory(wss_provider.clone(), cache_db, None);
+    // the backend stops once collected, or on shutdown
+    let backend_shutdown = shutdown.child_token();
+    let _backend_shutdown = backend_shutdown.clone().drop_guard();
+    let mut fork_factory =
+        ForkFactory::new_sandbox_factory(wss_provider.clone(), cache_db, None, backend_shutdown);
     inject_tax_checker_code(&mut fork_factory);
 
-    let mut pairs = Vec::new();
+    let mut pools = Vec::new();
+    let mut skipped = Vec::new();
+    let mut factory_lengths = HashMap::new();
 
     for factory_data in factorys {
         let progress_bar = create_progress_bar_with_message(
@@ -33,149 +68,300 @@ pub async fn get_all_pairs(
             &multi_progress_bar,
         );
 
+        let from = storage
+            .factory_lengths
+            .get(&factory_data.factory)
+            .copied()
+            .unwrap_or_default();
         let pairs_internal = get_all_pairs_via_batched_calls(
-            factory_data.factory,
+            &factory_data,
+            from,
             wss_provider.clone(),
             progress_bar.clone(),
         )
         .await;
+        factory_lengths.insert(factory_data.factory, pairs_internal.next_index());
+
+        let (kept, under): (Vec<_>, Vec<_>) =
+            pairs_internal.pairs.into_iter().partition(has_min_reserves);
+        pools.extend(kept);
+        skipped.extend(under);
 
         progress_bar.reset();
-        progress_bar.set_message("Getting tax".to_string());
-        progress_bar.set_length(pairs_internal.len() as u64);
+    }
 
-        let progress_bar_internal =
-            create_progress_bar_with_message("Spawning ".to_owned(), &multi_progress_bar);
+    // pairs skipped before are retried on their current reserves
+    if !storage.skipped.is_empty() {
+        let progress_bar = create_progress_bar_with_message(
+            "Getting reserves of skipped pools".to_string(),
+            &multi_progress_bar,
+        );
+        let addresses: Vec<Address> = storage.skipped.iter().map(|pool| pool.address).collect();
+        let reserves =
+            get_reserves_via_batched_calls(&addresses, wss_provider.clone(), progress_bar).await;
 
-        progress_bar_internal.set_length(pairs_internal.len() as u64);
+        for mut pool in storage.skipped.iter().cloned() {
+            if let Some((reserve0, reserve1)) = reserves.get(&pool.address) {
+                pool.reserve0 = *reserve0;
+                pool.reserve1 = *reserve1;
+            }
+            if has_min_reserves(&pool) {
+                pools.push(pool);
+            } else {
+                skipped.push(pool);
+            }
+        }
+    }
 
-        let mut tasks_batch = Vec::new();
-        let progress_bar_clone = progress_bar.clone();
+    // token -> stored taxes, stored pools with an unchecked token are checked again
+    let mut known_taxes = HashMap::new();
+    for pool in &storage.pools {
+        for (token, tax, safety) in [
+            (pool.token0, pool.fees0, pool.safety0),
+            (pool.token1, pool.fees1, pool.safety1),
+        ] {
+            if safety != TokenSafety::Unchecked {
+                known_taxes.insert(token, (tax, safety));
+            }
+        }
+    }
+    pools.extend(storage.pools.iter().cloned().filter(|pool| {
+        pool.safety0 == TokenSafety::Unchecked || pool.safety1 == TokenSafety::Unchecked
+    }));
 
-        for pool in pairs_internal {
-            insert_fake_approval(pool.token0, pool.address, &mut fork_factory);
-            let sand_box = fork_factory.new_sandbox_fork();
+    let mut checks = pick_token_checks(&pools);
+    checks.retain(|token, _| !known_taxes.contains_key(token));
 
-            if pool.reserve0 < U256::from(1000000) || pool.reserve1 < U256::from(1000000) {
-                progress_bar.inc(1);
-                progress_bar_internal.inc(1);
-                continue;
-            }
+    let mut slot_finder = SlotFinder::new();
+    let mut taxes = get_token_taxes(
+        checks,
+        &mut fork_factory,
+        &mut slot_finder,
+        current_block,
+        &multi_progress_bar,
+    )
+    .await;
+    // checks failing on the stopped backend would look like honeypots
+    if shutdown.is_cancelled() {
+        return None;
+    }
+    taxes.extend(known_taxes);
 
-            let factory_data_clone = factory_data.clone();
-            let current_block_clone = current_block;
-            let pool_clone = pool.clone();
-            let progress_bar_clone = progress_bar_clone.clone();
-
-            let task = tokio::task::spawn(async move {
-                let (buy_tax, sell_tax) = match get_tax(
-                    pool_clone.token0,
-                    pool_clone.address,
-                    sand_box,
-                    current_block_clone,
-                    factory_data_clone.fee,
-                )
-                .await
-                {
-                    Some(d) => d,
-                    None => {
-                        progress_bar_clone.inc(1);
-                        return None;
-                    }
-                };
+    // pools with a token that failed the tax check are retried on the next start, stored ones
+    // stay unchecked in `storage` and are retried anyway
+    let stored: HashSet<Address> = storage.pools.iter().map(|pool| pool.address).collect();
+    let mut pairs = Vec::new();
+    for mut pool in pools {
+        match (taxes.get(&pool.token0), taxes.get(&pool.token1)) {
+            (Some(tax0), Some(tax1)) => {
+                (pool.fees0, pool.safety0) = *tax0;
+                (pool.fees1, pool.safety1) = *tax1;
+                pairs.push(pool);
+            }
+            _ if stored.contains(&pool.address) => {}
+            _ => skipped.push(pool),
+        }
+    }
 
-                if buy_tax > U256::from(9970) || sell_tax > U256::from(9970) {
-                    progress_bar_clone.inc(1);
-                    return None;
-                }
+    info!("Spawning complete");
+    multi_progress_bar.clear().unwrap();
 
-                let mut cloned_pool = pool_clone.clone();
-                cloned_pool.fees0 = buy_tax;
-                cloned_pool.fees1 = sell_tax;
+    Some(CollectedPools {
+        pairs,
+        skipped,
+        factory_lengths,
+    })
+}
 
-                progress_bar_clone.inc(1);
-                Some(cloned_pool)
-            });
+fn has_min_reserves(pool: &UniV2Pool) -> bool {
+    pool.reserve0 >= U256::from(MIN_RESERVE) && pool.reserve1 >= U256::from(MIN_RESERVE)
+}
 
-            tasks_batch.push(task);
-            progress_bar_internal.inc(1);
+/// Picks the pool each token gets checked on, so that it's checked once no matter how many pools
+/// it is in
+///
+/// A token is measured against a WETH pool where possible, so that the other side of the
+/// trade is untaxed
+pub fn pick_token_checks(pools: &[UniV2Pool]) -> HashMap<Address, (Address, UniV2Pool)> {
+    let weth = get_weth_address();
 
-            if tasks_batch.len() >= 100 {
-                for task in &mut tasks_batch {
-                    if let Some(pair) = task.await.unwrap() {
-                        pairs.push(pair);
+    // token -> (quote token, pool)
+    let mut checks: HashMap<Address, (Address, UniV2Pool)> = HashMap::new();
+    for pool in pools {
+        for (token, quote) in [(pool.token0, pool.token1), (pool.token1, pool.token0)] {
+            let check = (quote, pool.clone());
+            match checks.entry(token) {
+                Entry::Vacant(entry) => {
+                    entry.insert(check);
+                }
+                Entry::Occupied(mut entry) => {
+                    if quote == weth && entry.get().0 != weth {
+                        entry.insert(check);
                     }
                 }
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                tasks_batch.clear();
             }
         }
+    }
 
-        // Handle any remaining tasks in the batch
-        for task in tasks_batch {
-            if let Some(pair) = task.await.unwrap() {
-                pairs.push(pair);
-            }
+    checks.remove(&weth);
+    checks
+}
+
+/// Checks the taxes and safety of every token in `checks` (see `pick_token_checks`)
+pub async fn get_token_taxes(
+    checks: HashMap<Address, (Address, UniV2Pool)>,
+    fork_factory: &mut ForkFactory,
+    slot_finder: &mut SlotFinder,
+    current_block: U64,
+    multi_progress_bar: &MultiProgress,
+) -> HashMap<Address, (TokenTax, TokenSafety)> {
+    let weth = get_weth_address();
+
+    let mut taxes = HashMap::new();
+    taxes.insert(weth, (TokenTax::default(), TokenSafety::Safe));
+
+    let progress_bar =
+        create_progress_bar_with_message("Getting tax".to_string(), multi_progress_bar);
+    progress_bar.set_length(checks.len() as u64);
+
+    let mut tasks_batch = Vec::new();
+
+    for (token, (quote, pool)) in checks {
+        let pair = pool.address;
+        // the tax checker pulls `quote` out of the pair to start the trade
+        if !insert_fake_approval(quote, pair, fork_factory, slot_finder, current_block) {
+            progress_bar.inc(1);
+            continue;
         }
+        // without it only the transfer tax check fails
+        insert_fake_approval(token, pair, fork_factory, slot_finder, current_block);
 
-        progress_bar.reset();
-        progress_bar_internal.reset();
+        let swap_sand_box = fork_factory.new_sandbox_fork();
+        let transfer_sand_box = fork_factory.new_sandbox_fork();
+
+        // the safety check trades up to 1% of the quote reserve
+        let quote_reserve = if pool.token0 == quote {
+            pool.reserve0
+        } else {
+            pool.reserve1
+        };
+        let safety_sand_box = if fund_senders(
+            quote,
+            quote_reserve,
+            fork_factory,
+            slot_finder,
+            current_block,
+        ) {
+            Some(fork_factory.new_sandbox_fork())
+        } else {
+            None
+        };
+
+        let progress_bar_clone = progress_bar.clone();
+
+        let task = tokio::task::spawn(async move {
+            let tax = get_token_tax(
+                token,
+                quote,
+                pair,
+                swap_sand_box,
+                transfer_sand_box,
+                current_block,
+                pool.router_fee,
+            )
+            .await;
+
+            // a token whose trades can't be simulated is not trusted
+            let safety = match safety_sand_box {
+                Some(sand_box) => get_token_safety(token, quote, &pool, sand_box, current_block),
+                None => TokenSafety::Limited,
+            };
+
+            progress_bar_clone.inc(1);
+            (token, tax.map(|tax| (tax, safety)))
+        });
+
+        tasks_batch.push(task);
+
+        if tasks_batch.len() >= 100 {
+            for task in &mut tasks_batch {
+                if let (token, Some(tax)) = task.await.unwrap() {
+                    taxes.insert(token, tax);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            tasks_batch.clear();
+        }
     }
 
-    info!("Spawning complete");
-    multi_progress_bar.clear().unwrap();
+    // Handle any remaining tasks in the batch
+    for task in tasks_batch {
+        if let (token, Some(tax)) = task.await.unwrap() {
+            taxes.insert(token, tax);
+        }
+    }
 
-    Some(pairs)
+    progress_bar.reset();
+    taxes
 }
 
+/// Refreshes the reserves of `pairs` with batched `getReserves` calls
+///
+/// Pools stored without the factory they came from (older checkpoints) get it, and the fee of
+/// its dex
 pub async fn update_reserves(
-    pairs: &mut Vec<UniV2Pool>,
-    factories: Vec<UniV2>,
+    pairs: &mut [UniV2Pool],
+    factories: &[UniV2],
     wss_provider: Arc<Provider<Ws>>,
 ) {
     let multi_progress_bar = MultiProgress::new();
-    let mut pairs_new: HashMap<H160, UniV2Pool> = HashMap::new();
-
-    for factory_data in factories {
-        let progress_bar = multi_progress_bar.add(ProgressBar::new(0));
-        progress_bar.set_style(
-            ProgressStyle::with_template("{msg} {bar:40.cyan/blue} {pos:>7}/{len:7}")
-                .expect("Error when setting progress bar style")
-                .progress_chars("##-"),
-        );
-
-        progress_bar.set_message(format!("Getting all pools from: {}", factory_data.factory));
-        let pairs_internal = get_all_pairs_via_batched_calls(
-            factory_data.factory,
-            wss_provider.clone(),
-            progress_bar.clone(),
-        )
-        .await;
-
-        progress_bar.finish_and_clear();
-        pairs_new.extend(pairs_internal.into_iter().map(|pair| (pair.address, pair)));
-    }
 
-    let banned_addresses = [
-        address("0xd46ba6d942050d489dbd938a2c909a5d5039a161"),
-        address("0x83B04AF7a77C727273B7a582D6Fda65472FCB3f2"),
-        address("0x9766d2e3f04AE13e8c2EB018eA51dC640d3f9f1F"),
-        address("0x7E3d39398C9574e1B4f9510Fd37aa3a47d602cDD"),
-    ];
+    let progress_bar =
+        create_progress_bar_with_message("Getting reserves".to_string(), &multi_progress_bar);
+    let addresses: Vec<Address> = pairs.iter().map(|pair| pair.address).collect();
+    let reserves =
+        get_reserves_via_batched_calls(&addresses, wss_provider.clone(), progress_bar).await;
 
-    pairs.retain(|pair| {
-        !banned_addresses.contains(&pair.token0)
-            && !banned_addresses.contains(&pair.token1)
-            && !banned_addresses.contains(&pair.address)
-    });
+    let unknown: Vec<Address> = pairs
+        .iter()
+        .filter(|pair| pair.factory.is_zero())
+        .map(|pair| pair.address)
+        .collect();
+    let pair_factories = if unknown.is_empty() {
+        HashMap::new()
+    } else {
+        let progress_bar =
+            create_progress_bar_with_message("Getting factories".to_string(), &multi_progress_bar);
+        get_factories_via_batched_calls(&unknown, wss_provider.clone(), progress_bar).await
+    };
 
+    let mut stale = 0;
     for pair in pairs {
-        if let Some(new_pair) = pairs_new.get(&pair.address) {
-            pair.reserve0 = new_pair.reserve0;
-            pair.reserve1 = new_pair.reserve1;
-        } else {
-            panic!("Not supposed to happen");
+        match reserves.get(&pair.address) {
+            Some((reserve0, reserve1)) => {
+                pair.reserve0 = *reserve0;
+                pair.reserve1 = *reserve1;
+            }
+            // in a batch that failed, the block updater catches up on its reserves
+            None => stale += 1,
         }
+
+        let dex = pair_factories
+            .get(&pair.address)
+            .and_then(|factory| factories.iter().find(|dex| dex.factory == *factory));
+        if let Some(dex) = dex {
+            pair.factory = dex.factory;
+            pair.router_fee = dex.fee;
+        }
+    }
+
+    multi_progress_bar.clear().unwrap();
+
+    if stale > 0 {
+        warn!(
+            "Kept stored reserves of {} pairs that could not be fetched",
+            stale
+        );
     }
 }
 
@@ -1,5 +1,6 @@
 pub mod collector;
 pub mod data_collector;
 pub mod tax_checker;
-
-
+pub mod throttle;
+pub mod token_info;
+pub mod token_safety;
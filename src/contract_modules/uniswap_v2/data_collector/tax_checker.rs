@@ -2,7 +2,10 @@ use std::str::FromStr;
 
 use crate::components::simulator::fork_db::ForkDB;
 use crate::components::simulator::fork_factory::ForkFactory;
+use crate::components::simulator::slot_finder::SlotFinder;
 use crate::contract_modules::uniswap_v2::constants::*;
+use crate::contract_modules::uniswap_v2::swap_math::FEE_DENOMINATOR;
+use crate::contract_modules::uniswap_v2::types::TokenTax;
 
 use ethers::abi::parse_abi;
 use ethers::prelude::*;
@@ -11,6 +14,39 @@ use revm::primitives::{Address as rAddress, Bytecode, U256 as rU256};
 use revm::primitives::{ExecutionResult, Output, TransactTo};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Measures the taxes of `token` by trading it against `quote` on `pair`
+///
+/// `quote` should be untaxed (eg: WETH), otherwise its own tax ends up in the result
+pub async fn get_token_tax(
+    token: Address,
+    quote: Address,
+    pair: Address,
+    swap_sand_box: ForkDB,
+    transfer_sand_box: ForkDB,
+    latest_block: U64,
+    fee: U256,
+) -> Option<TokenTax> {
+    // buying `token` with `quote` is taxed when it leaves the pair, selling it back when it enters
+    let (buy, sell) = get_tax(quote, pair, swap_sand_box, latest_block, fee).await?;
+
+    // a token that can't be moved between wallets is marked as fully taxed on transfer
+    let transfer = get_transfer_tax(token, pair, transfer_sand_box, latest_block)
+        .unwrap_or_else(|| U256::from(FEE_DENOMINATOR));
+
+    let verified_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    Some(TokenTax {
+        buy,
+        sell,
+        transfer,
+        verified_at,
+        changed: false,
+    })
+}
+
 pub async fn get_tax(
     token_in: Address,
     pair: Address,
@@ -18,6 +54,66 @@ pub async fn get_tax(
     latest_block: U64,
     fee: U256,
 ) -> Option<(U256, U256)> {
+    let mut evm = new_evm(sand_box_db, latest_block);
+
+    let tx_data = build_tax_checker_data(token_in, pair, U256::from(10000), fee);
+    let output = call(
+        &mut evm,
+        tax_checker_controller_address(),
+        tax_checker_address().0.into(),
+        tx_data,
+    )?;
+
+    let (buy_tax, sell_tax) = match decode_tax_checker_data(output) {
+        Ok(d) => d,
+        Err(_) => {
+            return None;
+        }
+    };
+
+    if buy_tax > fee || sell_tax > fee {
+        return None;
+    }
+
+    Some((buy_tax, sell_tax))
+}
+
+/// Moves part of the pair's `token` balance to a wallet, then on to a second wallet
+/// Requires `insert_fake_approval` for `token` and `pair`
+pub fn get_transfer_tax(
+    token: Address,
+    pair: Address,
+    sand_box_db: ForkDB,
+    latest_block: U64,
+) -> Option<U256> {
+    let mut evm = new_evm(sand_box_db, latest_block);
+    let erc20 = erc20_contract();
+
+    let holder: Address = tax_checker_holder_address().0.into();
+    let receiver: Address = tax_checker_receiver_address().0.into();
+
+    let amount = balance_of(&mut evm, &erc20, token, pair)? / 100;
+    if amount.is_zero() {
+        return None;
+    }
+
+    // leaving the pair may count as a buy, so only measure from the second hop
+    let data = erc20.encode("transferFrom", (pair, holder, amount)).ok()?;
+    call(&mut evm, tax_checker_address(), token, data)?;
+
+    let sent = balance_of(&mut evm, &erc20, token, holder)?;
+    if sent.is_zero() {
+        return None;
+    }
+
+    let data = erc20.encode("transfer", (receiver, sent)).ok()?;
+    call(&mut evm, holder.0.into(), token, data)?;
+
+    let received = balance_of(&mut evm, &erc20, token, receiver)?;
+    Some((sent - received.min(sent)) * U256::from(FEE_DENOMINATOR) / sent)
+}
+
+pub fn new_evm(sand_box_db: ForkDB, latest_block: U64) -> revm::EVM<ForkDB> {
     let mut evm: revm::EVM<ForkDB> = revm::EVM::new();
     evm.database(sand_box_db);
 
@@ -27,11 +123,19 @@ pub async fn get_tax(
     evm.env.block.coinbase =
         rAddress::from_str("0xDecafC0FFEe15BAD000000000000000000000000").unwrap();
 
-    let tx_data = build_tax_checker_data(token_in, pair, U256::from(10000), fee);
+    evm
+}
 
-    evm.env.tx.caller = tax_checker_controller_address();
-    evm.env.tx.transact_to = TransactTo::Call(tax_checker_address().0.into());
-    evm.env.tx.data = tx_data.0;
+// Commits a call from `caller` and returns its output, `None` if it failed
+pub fn call(
+    evm: &mut revm::EVM<ForkDB>,
+    caller: rAddress,
+    to: Address,
+    data: Bytes,
+) -> Option<Bytes> {
+    evm.env.tx.caller = caller;
+    evm.env.tx.transact_to = TransactTo::Call(to.0.into());
+    evm.env.tx.data = data.0;
     evm.env.tx.gas_limit = 7000000;
     evm.env.tx.gas_price = rU256::from(1000000000);
     evm.env.tx.value = rU256::ZERO;
@@ -43,31 +147,36 @@ pub async fn get_tax(
         }
     };
 
-    let output = match result {
+    match result {
         ExecutionResult::Success { output, .. } => match output {
-            Output::Call(o) => o,
-            Output::Create(o, _) => o,
+            Output::Call(o) => Some(o.into()),
+            Output::Create(o, _) => Some(o.into()),
         },
-        ExecutionResult::Revert { .. } => {
-            return None;
-        }
-        ExecutionResult::Halt { .. } => {
-            return None;
-        }
-    };
-
-    let (buy_tax, sell_tax) = match decode_tax_checker_data(output.into()) {
-        Ok(d) => d,
-        Err(_) => {
-            return None;
-        }
-    };
-
-    if buy_tax > U256::from(9970) || sell_tax > U256::from(9970) {
-        return None;
+        ExecutionResult::Revert { .. } => None,
+        ExecutionResult::Halt { .. } => None,
     }
+}
 
-    Some((buy_tax, sell_tax))
+pub fn balance_of(
+    evm: &mut revm::EVM<ForkDB>,
+    erc20: &BaseContract,
+    token: Address,
+    owner: Address,
+) -> Option<U256> {
+    let data = erc20.encode("balanceOf", owner).ok()?;
+    let output = call(evm, tax_checker_controller_address(), token, data)?;
+    erc20.decode_output("balanceOf", output).ok()
+}
+
+pub fn erc20_contract() -> BaseContract {
+    BaseContract::from(
+        parse_abi(&[
+            "function balanceOf(address) external view returns (uint256)",
+            "function transfer(address,uint256) external returns (bool)",
+            "function transferFrom(address,address,uint256) external returns (bool)",
+        ])
+        .unwrap(),
+    )
 }
 
 fn get_current_unix_time_seconds() -> u64 {
@@ -76,33 +185,33 @@ fn get_current_unix_time_seconds() -> u64 {
     since_epoch.as_secs()
 }
 
-pub fn insert_fake_approval(token: Address, pair: Address, fork_factory: &mut ForkFactory) {
-    for i in 0..100 {
-        let slot_new = map_location(U256::from(i), pair, tax_checker_address().0.into());
-        fork_factory
-            .insert_account_storage(
-                token.0.into(),
-                slot_new.into(),
-                rU256::from_str("10000000000000000000000000000000000000000000000").unwrap(),
-            )
-            .unwrap();
-    }
-}
-
-pub fn map_location(slot: U256, key: Address, key_after: Address) -> U256 {
-    let key_slot_hash: U256 = ethers::utils::keccak256(abi::encode(&[
-        abi::Token::Address(key),
-        abi::Token::Uint(slot),
-    ]))
-    .into();
-
-    let slot: U256 = ethers::utils::keccak256(abi::encode(&[
-        abi::Token::Address(key_after),
-        abi::Token::Uint(key_slot_hash),
-    ]))
-    .into();
+/// Lets the tax checker spend `pair`'s `token` balance
+/// Returns false if the token's allowance slot could not be found
+pub fn insert_fake_approval(
+    token: Address,
+    pair: Address,
+    fork_factory: &mut ForkFactory,
+    slot_finder: &mut SlotFinder,
+    latest_block: U64,
+) -> bool {
+    let location = match slot_finder.allowance_location(
+        token,
+        pair,
+        tax_checker_address().0.into(),
+        fork_factory,
+        latest_block,
+    ) {
+        Some(d) => d,
+        None => return false,
+    };
 
-    slot
+    fork_factory
+        .insert_account_storage(
+            token.0.into(),
+            location.into(),
+            rU256::from_str("10000000000000000000000000000000000000000000000").unwrap(),
+        )
+        .is_ok()
 }
 
 pub fn build_tax_checker_data(
@@ -140,4 +249,19 @@ pub fn inject_tax_checker_code(fork_factory: &mut ForkFactory) {
     let account =
         revm::primitives::AccountInfo::new(parse_ether(69).unwrap().into(), 0, Bytecode::default());
     fork_factory.insert_account_info(tax_checker_controller_address().0.into(), account);
+
+    // wallet sending the transfer in `get_transfer_tax` also pays gas
+    let account =
+        revm::primitives::AccountInfo::new(parse_ether(69).unwrap().into(), 0, Bytecode::default());
+    fork_factory.insert_account_info(tax_checker_holder_address().0.into(), account);
+
+    // wallets trading in `get_token_safety`
+    for sender in token_safety_sender_addresses() {
+        let account = revm::primitives::AccountInfo::new(
+            parse_ether(69).unwrap().into(),
+            0,
+            Bytecode::default(),
+        );
+        fork_factory.insert_account_info(sender, account);
+    }
 }
@@ -47,3 +47,61 @@ impl RequestThrottle {
         }
     }
 }
+
+/// `RequestThrottle` for async code, waits on the runtime's timer instead of blocking the
+/// thread, and can be shared between futures
+pub struct AsyncRequestThrottle {
+    requests_per_second_limit: usize,
+    // start of the current second, requests sent in it
+    window: tokio::sync::Mutex<(tokio::time::Instant, usize)>,
+}
+
+impl AsyncRequestThrottle {
+    /// 0 disables throttling
+    pub fn new(requests_per_second_limit: usize) -> AsyncRequestThrottle {
+        AsyncRequestThrottle {
+            requests_per_second_limit,
+            window: tokio::sync::Mutex::new((tokio::time::Instant::now(), 0)),
+        }
+    }
+
+    /// Waits until `inc` more requests fit in the budget of the current second
+    pub async fn increment_or_sleep(&self, inc: usize) {
+        if self.requests_per_second_limit == 0 {
+            return;
+        }
+
+        // held while waiting, so that requests go out in order
+        let mut window = self.window.lock().await;
+        let (started, requests) = &mut *window;
+        let second = Duration::from_secs(1);
+
+        if started.elapsed() < second && *requests >= self.requests_per_second_limit {
+            tokio::time::sleep_until(*started + second).await;
+        }
+        if started.elapsed() >= second {
+            *started = tokio::time::Instant::now();
+            *requests = 0;
+        }
+        *requests += inc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the clock only advances when every task waits, so the second passes at once
+    #[tokio::test(start_paused = true)]
+    async fn async_throttle_waits_for_the_next_second() {
+        let throttle = AsyncRequestThrottle::new(2);
+        let start = tokio::time::Instant::now();
+
+        throttle.increment_or_sleep(1).await;
+        throttle.increment_or_sleep(1).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        throttle.increment_or_sleep(1).await;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::contract_modules::uniswap_v2::{constants::get_token_info_batch_code, types::TokenInfo};
+use ethers::{
+    abi::{ParamType, Token},
+    providers::Middleware,
+    types::{Address, Bytes, TransactionRequest, U256},
+};
+use indicatif::ProgressBar;
+use log::*;
+use std::sync::Arc;
+
+// The returned data is the "runtime code" of the init code and is capped at 24576 bytes by
+// EIP-170, which is ~320 bytes per token (up to 672 with long names)
+const MAX_TOKENS_PER_BATCH: usize = 60;
+
+/// Fetches the metadata of `tokens` in a single `eth_call` by "deploying" a contract whose
+/// constructor does the calls and returns the results (same trick as the pairs batch request)
+pub async fn get_token_info_batch_request<M: Middleware>(
+    tokens: &[Address],
+    middleware: Arc<M>,
+) -> Option<Vec<(Address, TokenInfo)>> {
+    let constructor_args = ethers::abi::encode(&[Token::Array(
+        tokens.iter().map(|token| Token::Address(*token)).collect(),
+    )]);
+    let init_code: Bytes = [get_token_info_batch_code().0, constructor_args.into()]
+        .concat()
+        .into();
+
+    let tx = TransactionRequest::new().data(init_code);
+    let return_data = middleware.call(&tx.into(), None).await.ok()?;
+
+    let mut results = split_results(&return_data).into_iter();
+    let mut infos = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let (symbol, name, decimals) = (results.next()?, results.next()?, results.next()?);
+        let info = TokenInfo {
+            symbol: decode_string(symbol),
+            name: decode_string(name),
+            decimals: decode_decimals(decimals),
+        };
+        infos.push((*token, info));
+    }
+
+    Some(infos)
+}
+
+pub async fn get_all_token_info_via_batched_calls<M: 'static + Middleware>(
+    tokens: &[Address],
+    middleware: Arc<M>,
+    progress_bar: ProgressBar,
+) -> Vec<(Address, TokenInfo)> {
+    progress_bar.set_length(tokens.len() as u64);
+
+    let mut infos = vec![];
+    let mut pending: Vec<&[Address]> = tokens.chunks(MAX_TOKENS_PER_BATCH).rev().collect();
+    while let Some(batch) = pending.pop() {
+        match get_token_info_batch_request(batch, middleware.clone()).await {
+            Some(batch_infos) => {
+                infos.extend(batch_infos);
+                progress_bar.inc(batch.len() as u64);
+            }
+            // long names can still push a batch over the limit, retry both halves
+            None if batch.len() > 1 => {
+                let (first, second) = batch.split_at(batch.len() / 2);
+                pending.push(second);
+                pending.push(first);
+            }
+            // fetched again on the next run
+            None => {
+                warn!("Failed on fetching token info of {:?}", batch[0]);
+                progress_bar.inc(1);
+            }
+        }
+    }
+
+    infos
+}
+
+// Splits the returned (length, data padded to 32 bytes) entries
+fn split_results(return_data: &[u8]) -> Vec<&[u8]> {
+    let mut results = vec![];
+    let mut offset = 0;
+    while offset + 32 <= return_data.len() {
+        let length = U256::from_big_endian(&return_data[offset..offset + 32]).as_usize();
+        let start = offset + 32;
+        if start + length > return_data.len() {
+            break;
+        }
+
+        results.push(&return_data[start..start + length]);
+        offset = start + length.div_ceil(32) * 32;
+    }
+
+    results
+}
+
+// Most tokens return a `string`, some older ones (MKR, SAI) a `bytes32`
+fn decode_string(data: &[u8]) -> Option<String> {
+    let value = if data.len() == 32 {
+        let end = data.iter().position(|byte| *byte == 0).unwrap_or(32);
+        String::from_utf8(data[..end].to_vec()).ok()?
+    } else {
+        ethers::abi::decode(&[ParamType::String], data)
+            .ok()?
+            .pop()?
+            .into_string()?
+    };
+
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn decode_decimals(data: &[u8]) -> Option<u8> {
+    if data.len() != 32 {
+        return None;
+    }
+
+    let decimals = U256::from_big_endian(data);
+    if decimals > U256::from(u8::MAX) {
+        return None;
+    }
+
+    Some(decimals.as_u32() as u8)
+}
@@ -0,0 +1,292 @@
+use crate::components::simulator::fork_db::ForkDB;
+use crate::components::simulator::fork_factory::ForkFactory;
+use crate::components::simulator::slot_finder::SlotFinder;
+use crate::contract_modules::uniswap_v2::constants::token_safety_sender_addresses;
+use crate::contract_modules::uniswap_v2::data_collector::tax_checker::{
+    balance_of, call, erc20_contract, new_evm,
+};
+use crate::contract_modules::uniswap_v2::swap_math::{get_amount_out, FEE_DENOMINATOR};
+use crate::contract_modules::uniswap_v2::types::{TokenSafety, UniV2Pool};
+
+use ethers::abi::parse_abi;
+use ethers::prelude::*;
+use revm::primitives::U256 as rU256;
+
+// Trade sizes in basis points of the quote reserve
+const TRADE_SIZES: [u64; 3] = [1, 10, 100];
+// Blocks skipped before trading again, to catch taxes that change after launch
+const LATER_BLOCKS: u64 = 100;
+// Taxes within this many basis points of each other count as the same (rounding)
+const TAX_TOLERANCE: u64 = 10;
+// Anything taxed this much can't be sold at a profit
+const HONEYPOT_TAX: u64 = 9000;
+
+/// Outcome of buying a token and selling it back, `None` where the swap reverted
+#[derive(Debug, Clone, Copy)]
+pub struct Trade {
+    pub buy_tax: Option<U256>,
+    pub sell_tax: Option<U256>,
+}
+
+/// Trades `token` against `quote` on `pool` with different sizes, senders and blocks
+///
+/// Expects the senders to hold enough `quote` (see `token_safety_sender_addresses`)
+pub fn get_token_safety(
+    token: Address,
+    quote: Address,
+    pool: &UniV2Pool,
+    sand_box_db: ForkDB,
+    latest_block: U64,
+) -> TokenSafety {
+    let mut evm = new_evm(sand_box_db, latest_block);
+    let quote_reserve = if pool.token0 == quote {
+        pool.reserve0
+    } else {
+        pool.reserve1
+    };
+    let [first_sender, second_sender] = token_safety_sender_addresses();
+    let (first_sender, second_sender): (Address, Address) =
+        (first_sender.0.into(), second_sender.0.into());
+
+    let mut trades = Vec::new();
+
+    // buy and sell back in the same block, growing in size
+    for size in TRADE_SIZES {
+        let amount = quote_reserve * U256::from(size) / U256::from(FEE_DENOMINATOR);
+        trades.push(trade(&mut evm, pool, token, quote, amount, first_sender, 0));
+    }
+
+    // another wallet, selling one block after buying (cooldowns)
+    let amount = quote_reserve * U256::from(TRADE_SIZES[0]) / U256::from(FEE_DENOMINATOR);
+    advance_blocks(&mut evm, 1);
+    trades.push(trade(
+        &mut evm,
+        pool,
+        token,
+        quote,
+        amount,
+        second_sender,
+        1,
+    ));
+
+    // same trade a while later (taxes changing by block)
+    advance_blocks(&mut evm, LATER_BLOCKS);
+    trades.push(trade(
+        &mut evm,
+        pool,
+        token,
+        quote,
+        amount,
+        second_sender,
+        0,
+    ));
+
+    classify(&trades)
+}
+
+/// Gives both senders `amount` of `quote` to trade with
+pub fn fund_senders(
+    quote: Address,
+    amount: U256,
+    fork_factory: &mut ForkFactory,
+    slot_finder: &mut SlotFinder,
+    latest_block: U64,
+) -> bool {
+    token_safety_sender_addresses().into_iter().all(|sender| {
+        let location = match slot_finder.balance_location(
+            quote,
+            sender.0.into(),
+            fork_factory,
+            latest_block,
+        ) {
+            Some(d) => d,
+            None => return false,
+        };
+
+        fork_factory
+            .insert_account_storage(quote.0.into(), location.into(), amount.into())
+            .is_ok()
+    })
+}
+
+/// Safe and taxed tokens trade normally, limited ones only some of the time and honeypots
+/// can't be sold
+pub fn classify(trades: &[Trade]) -> TokenSafety {
+    if trades.iter().all(|trade| trade.sell_tax.is_none()) {
+        return TokenSafety::Honeypot;
+    }
+
+    let buy_taxes: Vec<U256> = trades.iter().filter_map(|trade| trade.buy_tax).collect();
+    let sell_taxes: Vec<U256> = trades.iter().filter_map(|trade| trade.sell_tax).collect();
+
+    let highest = buy_taxes
+        .iter()
+        .chain(&sell_taxes)
+        .max()
+        .copied()
+        .unwrap_or_default();
+    if highest >= U256::from(HONEYPOT_TAX) {
+        return TokenSafety::Honeypot;
+    }
+
+    if trades.iter().any(|trade| trade.sell_tax.is_none()) {
+        return TokenSafety::Limited;
+    }
+
+    // buy and sell taxes may differ, each has to stay the same across trades
+    if [buy_taxes, sell_taxes]
+        .iter()
+        .any(|taxes| spread(taxes) > U256::from(TAX_TOLERANCE))
+    {
+        return TokenSafety::Limited;
+    }
+
+    if highest > U256::from(TAX_TOLERANCE) {
+        TokenSafety::Taxed
+    } else {
+        TokenSafety::Safe
+    }
+}
+
+// Difference between the highest and the lowest tax
+fn spread(taxes: &[U256]) -> U256 {
+    let lowest = taxes.iter().min().copied().unwrap_or_default();
+    let highest = taxes.iter().max().copied().unwrap_or_default();
+    highest - lowest
+}
+
+// Buys `token` with `amount` of `quote` then sells everything back, `hold_blocks` later
+fn trade(
+    evm: &mut revm::EVM<ForkDB>,
+    pool: &UniV2Pool,
+    token: Address,
+    quote: Address,
+    amount: U256,
+    sender: Address,
+    hold_blocks: u64,
+) -> Trade {
+    let bought = swap(evm, pool, quote, amount, sender);
+    let buy_tax = bought.map(|(expected, received)| tax_of(expected, received));
+
+    let sell_tax = bought.and_then(|(_, received)| {
+        advance_blocks(evm, hold_blocks);
+        let (expected, received) = swap(evm, pool, token, received, sender)?;
+        Some(tax_of(expected, received))
+    });
+
+    Trade { buy_tax, sell_tax }
+}
+
+// Swaps directly on the pair, returns (untaxed amount out, amount received)
+fn swap(
+    evm: &mut revm::EVM<ForkDB>,
+    pool: &UniV2Pool,
+    token_in: Address,
+    amount_in: U256,
+    sender: Address,
+) -> Option<(U256, U256)> {
+    let erc20 = erc20_contract();
+    let pair_contract = pair_contract();
+    let token_out = if token_in == pool.token0 {
+        pool.token1
+    } else {
+        pool.token0
+    };
+
+    let data = pair_contract.encode("getReserves", ()).ok()?;
+    let output = call(evm, sender.0.into(), pool.address, data)?;
+    let (reserve0, reserve1, _): (U256, U256, u32) =
+        pair_contract.decode_output("getReserves", output).ok()?;
+    let (reserve_in, reserve_out) = if token_in == pool.token0 {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+
+    let balance_before = balance_of(evm, &erc20, token_out, sender)?;
+
+    let data = erc20.encode("transfer", (pool.address, amount_in)).ok()?;
+    call(evm, sender.0.into(), token_in, data)?;
+
+    // the pair pays out for what it received, the rest is tax
+    let received_by_pair =
+        balance_of(evm, &erc20, token_in, pool.address)?.saturating_sub(reserve_in);
+    let expected = get_amount_out(amount_in, reserve_in, reserve_out, pool.router_fee)?;
+    let amount_out = get_amount_out(received_by_pair, reserve_in, reserve_out, pool.router_fee)?;
+
+    let (amount0_out, amount1_out) = if token_in == pool.token0 {
+        (U256::zero(), amount_out)
+    } else {
+        (amount_out, U256::zero())
+    };
+    let data = pair_contract
+        .encode("swap", (amount0_out, amount1_out, sender, Bytes::new()))
+        .ok()?;
+    call(evm, sender.0.into(), pool.address, data)?;
+
+    let balance_after = balance_of(evm, &erc20, token_out, sender)?;
+    Some((expected, balance_after.saturating_sub(balance_before)))
+}
+
+fn tax_of(expected: U256, received: U256) -> U256 {
+    if expected.is_zero() {
+        return U256::zero();
+    }
+
+    expected.saturating_sub(received) * U256::from(FEE_DENOMINATOR) / expected
+}
+
+fn advance_blocks(evm: &mut revm::EVM<ForkDB>, blocks: u64) {
+    evm.env.block.number += rU256::from(blocks);
+    evm.env.block.timestamp += rU256::from(blocks * 12);
+}
+
+fn pair_contract() -> BaseContract {
+    BaseContract::from(
+        parse_abi(&[
+            "function getReserves() external view returns (uint112,uint112,uint32)",
+            "function swap(uint256,uint256,address,bytes) external",
+        ])
+        .unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(buy_tax: u64, sell_tax: Option<u64>) -> Trade {
+        Trade {
+            buy_tax: Some(U256::from(buy_tax)),
+            sell_tax: sell_tax.map(U256::from),
+        }
+    }
+
+    #[test]
+    fn constant_buy_and_sell_taxes_are_taxed() {
+        let trades = [trade(0, Some(500)); 5];
+        assert_eq!(classify(&trades), TokenSafety::Taxed);
+    }
+
+    #[test]
+    fn changing_sell_tax_is_limited() {
+        let trades = [
+            trade(0, Some(500)),
+            trade(0, Some(500)),
+            trade(0, Some(1500)),
+        ];
+        assert_eq!(classify(&trades), TokenSafety::Limited);
+    }
+
+    #[test]
+    fn untaxed_token_is_safe() {
+        let trades = [trade(3, Some(0)), trade(0, Some(5))];
+        assert_eq!(classify(&trades), TokenSafety::Safe);
+    }
+
+    #[test]
+    fn unsellable_token_is_a_honeypot() {
+        assert_eq!(classify(&[trade(0, None); 3]), TokenSafety::Honeypot);
+        assert_eq!(classify(&[trade(0, Some(9500))]), TokenSafety::Honeypot);
+    }
+}
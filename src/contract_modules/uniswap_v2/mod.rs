@@ -1,8 +1,14 @@
+pub mod ban_list;
 pub mod bindings;
 pub mod calc;
 pub mod checkpoint;
 pub mod constants;
 pub mod data_collector;
+pub mod pool_filter;
+pub mod router_calls;
+pub mod sandwich;
+pub mod swap_math;
+pub mod token_registry;
 pub mod types;
 
 use std::str::FromStr;
@@ -0,0 +1,248 @@
+use ethers::prelude::*;
+use ethers::utils::parse_ether;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use super::constants::get_weth_address;
+use super::types::UniV2Pool;
+use crate::constants::SWAP_TOPIC;
+
+// Tokens are priced at most this many pools away from WETH, same as the longest cycle
+const MAX_PRICE_HOPS: usize = 3;
+// Blocks per `eth_getLogs` request when counting swaps
+const LOGS_STEP: u64 = 500;
+
+/// Decides which stored pools `State` indexes, evaluated again on every start
+///
+/// Configured through the env:
+/// * `MIN_TVL_WETH`: minimum value of both reserves together, in WETH (default 1)
+/// * `MIN_RECENT_SWAPS`: minimum swaps in the last `ACTIVITY_BLOCKS` blocks (default 0, off)
+/// * `ACTIVITY_BLOCKS`: default 7200, about a day
+/// * `ALLOWED_TOKENS`: comma separated, when not empty only pools of these tokens (and WETH) are
+///   kept
+/// * `DENIED_TOKENS`: comma separated, pools of these tokens are dropped
+#[derive(Debug, Clone)]
+pub struct PoolFilter {
+    pub min_tvl_weth: U256,
+    pub min_recent_swaps: u64,
+    pub activity_blocks: u64,
+    pub allowed_tokens: Option<HashSet<Address>>,
+    pub denied_tokens: HashSet<Address>,
+}
+
+impl Default for PoolFilter {
+    fn default() -> Self {
+        Self {
+            min_tvl_weth: parse_ether(1).unwrap(),
+            min_recent_swaps: 0,
+            activity_blocks: 7200,
+            allowed_tokens: None,
+            denied_tokens: HashSet::new(),
+        }
+    }
+}
+
+impl PoolFilter {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        let min_tvl_weth = match std::env::var("MIN_TVL_WETH") {
+            Ok(value) => parse_ether(value).expect("invalid MIN_TVL_WETH"),
+            Err(_) => default.min_tvl_weth,
+        };
+        let min_recent_swaps = match std::env::var("MIN_RECENT_SWAPS") {
+            Ok(value) => value.parse().expect("invalid MIN_RECENT_SWAPS"),
+            Err(_) => default.min_recent_swaps,
+        };
+        let activity_blocks = match std::env::var("ACTIVITY_BLOCKS") {
+            Ok(value) => value.parse().expect("invalid ACTIVITY_BLOCKS"),
+            Err(_) => default.activity_blocks,
+        };
+
+        Self {
+            min_tvl_weth,
+            min_recent_swaps,
+            activity_blocks,
+            allowed_tokens: std::env::var("ALLOWED_TOKENS")
+                .ok()
+                .map(|value| parse_tokens(&value))
+                .filter(|tokens| !tokens.is_empty()),
+            denied_tokens: std::env::var("DENIED_TOKENS")
+                .map(|value| parse_tokens(&value))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Pools passing every filter
+    pub async fn apply(
+        &self,
+        pools: &[UniV2Pool],
+        provider: Arc<Provider<Ws>>,
+        block: U64,
+    ) -> Vec<UniV2Pool> {
+        let weth = get_weth_address();
+        let min_tvl = u256_to_f64(self.min_tvl_weth);
+        // a pool too small to be watched can't be trusted to price a token either
+        let prices = weth_prices(pools, min_tvl / 2.0);
+
+        let swaps = if self.min_recent_swaps > 0 {
+            let from = block.as_u64().saturating_sub(self.activity_blocks);
+            count_swaps(provider, from, block.as_u64()).await
+        } else {
+            None
+        };
+
+        let (mut listed, mut small, mut inactive) = (0, 0, 0);
+        let filtered: Vec<UniV2Pool> = pools
+            .iter()
+            .filter(|pool| {
+                let token_allowed = |token: &Address| {
+                    !self.denied_tokens.contains(token)
+                        && match &self.allowed_tokens {
+                            Some(allowed) => *token == weth || allowed.contains(token),
+                            None => true,
+                        }
+                };
+                if !token_allowed(&pool.token0) || !token_allowed(&pool.token1) {
+                    listed += 1;
+                    return false;
+                }
+
+                match tvl_in_weth(pool, &prices) {
+                    Some(tvl) if tvl >= min_tvl => {}
+                    _ => {
+                        small += 1;
+                        return false;
+                    }
+                }
+
+                if let Some(swaps) = &swaps {
+                    if swaps.get(&pool.address).copied().unwrap_or(0) < self.min_recent_swaps {
+                        inactive += 1;
+                        return false;
+                    }
+                }
+
+                true
+            })
+            .cloned()
+            .collect();
+
+        info!(
+            "Pool filter kept {} of {} pools | token lists: {} tvl: {} activity: {}",
+            filtered.len(),
+            pools.len(),
+            listed,
+            small,
+            inactive
+        );
+
+        filtered
+    }
+}
+
+/// Price of one raw unit of each token in wei, found by walking out from WETH through pools
+/// holding at least `min_side_value` wei on the already priced side
+pub fn weth_prices(pools: &[UniV2Pool], min_side_value: f64) -> HashMap<Address, f64> {
+    let mut prices = HashMap::new();
+    prices.insert(get_weth_address(), 1.0);
+
+    for _ in 0..MAX_PRICE_HOPS {
+        // token -> (value of the priced side, price), the deepest pool wins
+        let mut found: HashMap<Address, (f64, f64)> = HashMap::new();
+
+        for pool in pools {
+            let (reserve0, reserve1) = (u256_to_f64(pool.reserve0), u256_to_f64(pool.reserve1));
+            if reserve0 == 0.0 || reserve1 == 0.0 {
+                continue;
+            }
+
+            let (priced, reserve_priced, token, reserve) =
+                match (prices.get(&pool.token0), prices.get(&pool.token1)) {
+                    (Some(price), None) => (*price, reserve0, pool.token1, reserve1),
+                    (None, Some(price)) => (*price, reserve1, pool.token0, reserve0),
+                    _ => continue,
+                };
+
+            let value = priced * reserve_priced;
+            if value < min_side_value {
+                continue;
+            }
+
+            let entry = found.entry(token).or_insert((0.0, 0.0));
+            if value > entry.0 {
+                *entry = (value, value / reserve);
+            }
+        }
+
+        if found.is_empty() {
+            break;
+        }
+        prices.extend(found.into_iter().map(|(token, (_, price))| (token, price)));
+    }
+
+    prices
+}
+
+/// Value of both reserves in wei, `None` if a token couldn't be priced
+pub fn tvl_in_weth(pool: &UniV2Pool, prices: &HashMap<Address, f64>) -> Option<f64> {
+    let price0 = prices.get(&pool.token0)?;
+    let price1 = prices.get(&pool.token1)?;
+
+    Some(u256_to_f64(pool.reserve0) * price0 + u256_to_f64(pool.reserve1) * price1)
+}
+
+// Swap events per pool over `from..=to`, `None` if the logs couldn't be fetched
+//
+// Sync also fires on mint, burn and `sync`, only Swap is a trade
+async fn count_swaps(
+    provider: Arc<Provider<Ws>>,
+    from: u64,
+    to: u64,
+) -> Option<HashMap<Address, u64>> {
+    let swap_topic = H256::from_slice(&hex::decode(SWAP_TOPIC).unwrap());
+    let mut swaps = HashMap::new();
+
+    let mut start = from;
+    while start <= to {
+        let end = (start + LOGS_STEP - 1).min(to);
+        let filter = Filter::new()
+            .topic0(swap_topic)
+            .from_block(start)
+            .to_block(end);
+
+        let logs = match provider.get_logs(&filter).await {
+            Ok(d) => d,
+            Err(error) => {
+                warn!(
+                    "Skipping the activity filter, failed on getting logs: {}",
+                    error
+                );
+                return None;
+            }
+        };
+        for log in logs {
+            *swaps.entry(log.address).or_insert(0) += 1;
+        }
+
+        start = end + 1;
+    }
+
+    Some(swaps)
+}
+
+pub fn parse_tokens(value: &str) -> HashSet<Address> {
+    value
+        .split(',')
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.parse().expect("invalid token address"))
+        .collect()
+}
+
+pub fn u256_to_f64(value: U256) -> f64 {
+    value.0.iter().rev().fold(0.0, |total, limb| {
+        total * 18446744073709551616.0 + *limb as f64
+    })
+}
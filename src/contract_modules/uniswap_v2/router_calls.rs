@@ -0,0 +1,352 @@
+use ethers::abi::AbiDecode;
+use ethers::prelude::*;
+use ethers::utils::{get_create2_address_from_hash, keccak256};
+use serde::Serialize;
+
+use super::bindings::uni_v2_router::UniV2RouterCalls;
+use super::swap_math::{get_amount_in, get_amount_out};
+use super::types::{TokenSafety, UniV2};
+use crate::state::State;
+
+/// What the sender accepts, as encoded in the router call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapLimit {
+    ExactIn {
+        amount_in: U256,
+        amount_out_min: U256,
+    },
+    ExactOut {
+        amount_out: U256,
+        amount_in_max: U256,
+    },
+}
+
+/// End of the path the router wraps ETH into or unwraps it from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EthSide {
+    In,
+    Out,
+}
+
+/// A pending swap through a Uniswap V2 router
+#[derive(Debug, Clone)]
+pub struct RouterSwap {
+    pub router: Address,
+    pub path: Vec<Address>,
+    pub limit: SwapLimit,
+    // the router reverts in a block with a later timestamp
+    pub deadline: U256,
+    // `None` for token to token swaps
+    pub eth: Option<EthSide>,
+}
+
+impl RouterSwap {
+    /// Whether the path has `weth` on the end the router wraps or unwraps ETH
+    pub fn wraps(&self, weth: Address) -> bool {
+        match self.eth {
+            None => true,
+            Some(EthSide::In) => self.path.first() == Some(&weth),
+            Some(EthSide::Out) => self.path.last() == Some(&weth),
+        }
+    }
+}
+
+/// One pool of a swap's path, oriented in the swap direction
+#[derive(Debug, Clone, Copy)]
+pub struct SwapHop {
+    pub pool: Address,
+    // `path[i] < path[i + 1]`, the pool's reserve0 is the input side
+    pub zero_for_one: bool,
+    pub reserve_in: U256,
+    pub reserve_out: U256,
+    pub fee: U256,
+}
+
+pub fn decode_router_swap(tx: &Transaction) -> Option<RouterSwap> {
+    let call = UniV2RouterCalls::decode(&tx.input).ok()?;
+
+    let (path, limit, deadline, eth) = match call {
+        UniV2RouterCalls::SwapExactTokensForTokens(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: d.amount_in,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            None,
+        ),
+        UniV2RouterCalls::SwapExactTokensForTokensSupportingFeeOnTransferTokens(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: d.amount_in,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            None,
+        ),
+        UniV2RouterCalls::SwapExactTokensForETH(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: d.amount_in,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            Some(EthSide::Out),
+        ),
+        UniV2RouterCalls::SwapExactTokensForETHSupportingFeeOnTransferTokens(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: d.amount_in,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            Some(EthSide::Out),
+        ),
+        UniV2RouterCalls::SwapExactETHForTokens(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: tx.value,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            Some(EthSide::In),
+        ),
+        UniV2RouterCalls::SwapExactETHForTokensSupportingFeeOnTransferTokens(d) => (
+            d.path,
+            SwapLimit::ExactIn {
+                amount_in: tx.value,
+                amount_out_min: d.amount_out_min,
+            },
+            d.deadline,
+            Some(EthSide::In),
+        ),
+        UniV2RouterCalls::SwapTokensForExactTokens(d) => (
+            d.path,
+            SwapLimit::ExactOut {
+                amount_out: d.amount_out,
+                amount_in_max: d.amount_in_max,
+            },
+            d.deadline,
+            None,
+        ),
+        UniV2RouterCalls::SwapTokensForExactETH(d) => (
+            d.path,
+            SwapLimit::ExactOut {
+                amount_out: d.amount_out,
+                amount_in_max: d.amount_in_max,
+            },
+            d.deadline,
+            Some(EthSide::Out),
+        ),
+        UniV2RouterCalls::SwapETHForExactTokens(d) => (
+            d.path,
+            SwapLimit::ExactOut {
+                amount_out: d.amount_out,
+                amount_in_max: tx.value,
+            },
+            d.deadline,
+            Some(EthSide::In),
+        ),
+        _ => return None,
+    };
+
+    if path.len() < 2 {
+        return None;
+    }
+
+    Some(RouterSwap {
+        router: tx.to?,
+        path,
+        limit,
+        deadline,
+        eth,
+    })
+}
+
+/// Pools of `swap`'s path at the reserves in `state`
+/// `None` if one isn't tracked by `state` or has a taxed token
+pub fn swap_hops(swap: &RouterSwap, dex: &UniV2, state: &State) -> Option<Vec<SwapHop>> {
+    swap.path
+        .windows(2)
+        .map(|tokens| {
+            let pool = pair_address(dex, tokens[0], tokens[1]);
+            let pair = state
+                .address_mapping
+                .get(&pool)
+                .and_then(|index| state.pairs_mapping.get(index))?
+                .borrow();
+
+            if pair.safety0 != TokenSafety::Safe || pair.safety1 != TokenSafety::Safe {
+                return None;
+            }
+
+            let zero_for_one = pair.token0 == tokens[0];
+            let (reserve_in, reserve_out) = if zero_for_one {
+                (pair.reserve0, pair.reserve1)
+            } else {
+                (pair.reserve1, pair.reserve0)
+            };
+
+            Some(SwapHop {
+                pool,
+                zero_for_one,
+                reserve_in,
+                reserve_out,
+                fee: pair.router_fee,
+            })
+        })
+        .collect()
+}
+
+/// Same as `UniswapV2Library.pairFor`
+pub fn pair_address(dex: &UniV2, token_a: Address, token_b: Address) -> Address {
+    let (token0, token1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+    let salt = keccak256([token0.as_bytes(), token1.as_bytes()].concat());
+
+    get_create2_address_from_hash(dex.factory, salt, dex.init_code_hash)
+}
+
+/// Amount of every token along the path, as the router's `getAmountsOut`/`getAmountsIn`
+/// `None` if the swap fails `limit` and would revert
+pub fn swap_amounts(limit: SwapLimit, hops: &[SwapHop]) -> Option<Vec<U256>> {
+    match limit {
+        SwapLimit::ExactIn {
+            amount_in,
+            amount_out_min,
+        } => {
+            let mut amounts = vec![amount_in];
+            for hop in hops {
+                let amount_out =
+                    get_amount_out(*amounts.last()?, hop.reserve_in, hop.reserve_out, hop.fee)?;
+                amounts.push(amount_out);
+            }
+
+            (*amounts.last()? >= amount_out_min).then_some(amounts)
+        }
+        SwapLimit::ExactOut {
+            amount_out,
+            amount_in_max,
+        } => {
+            let mut amounts = vec![amount_out];
+            for hop in hops.iter().rev() {
+                let amount_in =
+                    get_amount_in(*amounts.last()?, hop.reserve_in, hop.reserve_out, hop.fee)?;
+                amounts.push(amount_in);
+            }
+            amounts.reverse();
+
+            (amounts[0] <= amount_in_max).then_some(amounts)
+        }
+    }
+}
+
+/// Reserves `[reserve0, reserve1]` of every hop once the swap of `amounts` went through
+pub fn reserves_after(hops: &[SwapHop], amounts: &[U256]) -> Option<Vec<(Address, [U256; 2])>> {
+    hops.iter()
+        .zip(amounts.windows(2))
+        .map(|(hop, amounts)| {
+            let reserve_in = hop.reserve_in.checked_add(amounts[0])?;
+            let reserve_out = hop.reserve_out.checked_sub(amounts[1])?;
+
+            let reserves = if hop.zero_for_one {
+                [reserve_in, reserve_out]
+            } else {
+                [reserve_out, reserve_in]
+            };
+            Some((hop.pool, reserves))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bindings::uni_v2_router::SwapExactETHForTokensCall;
+    use super::*;
+    use ethers::abi::AbiEncode;
+
+    fn hop(zero_for_one: bool, reserve_in: u64, reserve_out: u64) -> SwapHop {
+        SwapHop {
+            pool: Address::from_low_u64_be(reserve_in),
+            zero_for_one,
+            reserve_in: reserve_in.into(),
+            reserve_out: reserve_out.into(),
+            fee: 9970.into(),
+        }
+    }
+
+    #[test]
+    fn reserves_follow_the_swap() {
+        let hops = [
+            hop(true, 1_000_000, 2_000_000),
+            hop(false, 3_000_000, 1_000_000),
+        ];
+        let limit = SwapLimit::ExactIn {
+            amount_in: 10_000.into(),
+            amount_out_min: 0.into(),
+        };
+
+        let amounts = swap_amounts(limit, &hops).unwrap();
+        let reserves = reserves_after(&hops, &amounts).unwrap();
+
+        assert_eq!(
+            reserves[0].1,
+            [U256::from(1_010_000), U256::from(2_000_000) - amounts[1]]
+        );
+        assert_eq!(
+            reserves[1].1,
+            [
+                U256::from(1_000_000) - amounts[2],
+                U256::from(3_000_000) + amounts[1]
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_failing_swap_reverts() {
+        let hops = [hop(true, 1_000_000, 1_000_000)];
+        let amount_in = U256::from(10_000);
+        let quoted = swap_amounts(
+            SwapLimit::ExactIn {
+                amount_in,
+                amount_out_min: 0.into(),
+            },
+            &hops,
+        )
+        .unwrap()[1];
+
+        let limit = SwapLimit::ExactIn {
+            amount_in,
+            amount_out_min: quoted + 1,
+        };
+        assert!(swap_amounts(limit, &hops).is_none());
+    }
+
+    #[test]
+    fn eth_swap_keeps_its_deadline_and_weth_end() {
+        let weth = Address::from_low_u64_be(1);
+        let token = Address::from_low_u64_be(2);
+        let call = UniV2RouterCalls::SwapExactETHForTokens(SwapExactETHForTokensCall {
+            amount_out_min: 0.into(),
+            path: vec![weth, token],
+            to: Address::zero(),
+            deadline: 1_700_000_000.into(),
+        });
+        let tx = Transaction {
+            to: Some(Address::from_low_u64_be(3)),
+            value: 1000.into(),
+            input: call.encode().into(),
+            ..Default::default()
+        };
+
+        let swap = decode_router_swap(&tx).unwrap();
+        assert_eq!(swap.deadline, U256::from(1_700_000_000));
+        assert_eq!(swap.eth, Some(EthSide::In));
+        assert!(swap.wraps(weth));
+        assert!(!swap.wraps(token));
+    }
+}
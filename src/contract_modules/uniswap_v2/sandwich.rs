@@ -0,0 +1,233 @@
+use ethers::prelude::*;
+use serde::Serialize;
+
+use super::router_calls::{decode_router_swap, swap_amounts, swap_hops, SwapHop, SwapLimit};
+use super::swap_math::get_amount_out;
+use super::types::UniV2;
+use crate::state::State;
+
+// The frontrun search stops at this many times the pool's input reserve, a victim without
+// slippage protection can otherwise be sandwiched by any amount
+const MAX_FRONTRUN_RESERVE_MULTIPLE: u64 = 10;
+
+/// Most a sandwich on one pool of the victim's path could extract, research only
+#[derive(Debug, Clone, Serialize)]
+pub struct SandwichReport {
+    pub tx: TxHash,
+    pub router: Address,
+    pub path: Vec<Address>,
+    pub limit: SwapLimit,
+    pub pool: Address,
+    // token the frontrun sells into `pool`, the profit is in it too
+    pub token_in: Address,
+    pub token_out: Address,
+    pub frontrun_in: U256,
+    pub frontrun_out: U256,
+    pub backrun_out: U256,
+    // backrun_out - frontrun_in, before gas
+    pub profit: String,
+    // the victim's limit wasn't reached before the search bound
+    pub capped: bool,
+}
+
+/// Most profitable sandwich on a single pool of `tx`'s path at the reserves in `state`
+/// `None` if `tx` isn't a swap through one of `dexes`, touches a pool `state` doesn't track or
+/// a taxed token, or leaves no profit
+pub fn analyze_sandwich(
+    tx: &Transaction,
+    dexes: &[UniV2],
+    state: &State,
+) -> Option<SandwichReport> {
+    let swap = decode_router_swap(tx)?;
+    let dex = dexes.iter().find(|dex| dex.router == swap.router)?;
+    let hops = swap_hops(&swap, dex, state)?;
+
+    let mut best: Option<(I256, SandwichReport)> = None;
+    for index in 0..hops.len() {
+        // rounding lets a few wei through even without slippage, that's nothing to extract
+        let sandwich = match max_sandwich(swap.limit, &hops, index) {
+            Some(d) if d.profit() > I256::zero() => d,
+            _ => continue,
+        };
+
+        let report = SandwichReport {
+            tx: tx.hash,
+            router: swap.router,
+            path: swap.path.clone(),
+            limit: swap.limit,
+            pool: hops[index].pool,
+            token_in: swap.path[index],
+            token_out: swap.path[index + 1],
+            frontrun_in: sandwich.frontrun_in,
+            frontrun_out: sandwich.frontrun_out,
+            backrun_out: sandwich.backrun_out,
+            profit: sandwich.profit().to_string(),
+            capped: sandwich.capped,
+        };
+
+        let is_better = match &best {
+            Some((profit, _)) => sandwich.profit() > *profit,
+            None => true,
+        };
+        if is_better {
+            best = Some((sandwich.profit(), report));
+        }
+    }
+
+    best.map(|(_, report)| report)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sandwich {
+    frontrun_in: U256,
+    frontrun_out: U256,
+    backrun_out: U256,
+    capped: bool,
+}
+
+impl Sandwich {
+    fn profit(&self) -> I256 {
+        I256::from_raw(self.backrun_out) - I256::from_raw(self.frontrun_in)
+    }
+}
+
+// Frontruns `hops[index]` with `frontrun_in`, returns the changed hops and the frontrun output
+fn frontrun(hops: &[SwapHop], index: usize, frontrun_in: U256) -> Option<(Vec<SwapHop>, U256)> {
+    let hop = hops[index];
+    let frontrun_out = get_amount_out(frontrun_in, hop.reserve_in, hop.reserve_out, hop.fee)?;
+
+    let mut hops = hops.to_vec();
+    hops[index].reserve_in = hop.reserve_in.checked_add(frontrun_in)?;
+    hops[index].reserve_out = hop.reserve_out - frontrun_out;
+
+    Some((hops, frontrun_out))
+}
+
+// Whether the victim still goes through after a frontrun of `frontrun_in`
+fn victim_fills(limit: SwapLimit, hops: &[SwapHop], index: usize, frontrun_in: U256) -> bool {
+    match frontrun(hops, index, frontrun_in) {
+        Some((hops, _)) => swap_amounts(limit, &hops).is_some(),
+        None => false,
+    }
+}
+
+// Largest frontrun on `hops[index]` the victim's limit tolerates, and the backrun selling
+// its output back after the victim
+fn max_sandwich(limit: SwapLimit, hops: &[SwapHop], index: usize) -> Option<Sandwich> {
+    swap_amounts(limit, hops)?;
+
+    let bound = hops[index]
+        .reserve_in
+        .saturating_mul(MAX_FRONTRUN_RESERVE_MULTIPLE.into());
+    let (mut low, mut high) = (U256::zero(), bound);
+    let capped = victim_fills(limit, hops, index, bound);
+
+    if capped {
+        low = bound;
+    } else {
+        // `low` always fills and `high` never does
+        while high - low > U256::one() {
+            let middle = low + (high - low) / 2;
+            if victim_fills(limit, hops, index, middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+    }
+
+    if low.is_zero() {
+        return None;
+    }
+
+    let (hops, frontrun_out) = frontrun(hops, index, low)?;
+    let amounts = swap_amounts(limit, &hops)?;
+    let hop = hops[index];
+    let backrun_out = get_amount_out(
+        frontrun_out,
+        hop.reserve_out - amounts[index + 1],
+        hop.reserve_in + amounts[index],
+        hop.fee,
+    )?;
+
+    Some(Sandwich {
+        frontrun_in: low,
+        frontrun_out,
+        backrun_out,
+        capped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(reserve_in: u64, reserve_out: u64) -> SwapHop {
+        SwapHop {
+            pool: Address::zero(),
+            zero_for_one: true,
+            reserve_in: reserve_in.into(),
+            reserve_out: reserve_out.into(),
+            fee: 9970.into(),
+        }
+    }
+
+    #[test]
+    fn frontrun_stops_at_victim_limit() {
+        let hops = [hop(1_000_000_000, 1_000_000_000)];
+        let amount_in = U256::from(10_000_000);
+        let quoted = swap_amounts(
+            SwapLimit::ExactIn {
+                amount_in,
+                amount_out_min: 0.into(),
+            },
+            &hops,
+        )
+        .unwrap()[1];
+        // 1% slippage
+        let limit = SwapLimit::ExactIn {
+            amount_in,
+            amount_out_min: quoted * 99 / 100,
+        };
+
+        let sandwich = max_sandwich(limit, &hops, 0).unwrap();
+        assert!(!sandwich.capped);
+        assert!(victim_fills(limit, &hops, 0, sandwich.frontrun_in));
+        assert!(!victim_fills(limit, &hops, 0, sandwich.frontrun_in + 1));
+    }
+
+    #[test]
+    fn nothing_to_extract_without_slippage() {
+        let hops = [hop(1_000_000_000, 1_000_000_000)];
+        let amount_out = U256::from(10_000_000);
+        let exact = swap_amounts(
+            SwapLimit::ExactOut {
+                amount_out,
+                amount_in_max: U256::MAX,
+            },
+            &hops,
+        )
+        .unwrap()[0];
+        let limit = SwapLimit::ExactOut {
+            amount_out,
+            amount_in_max: exact,
+        };
+
+        if let Some(sandwich) = max_sandwich(limit, &hops, 0) {
+            assert!(sandwich.profit() <= I256::zero());
+        }
+    }
+
+    #[test]
+    fn unprotected_victim_is_capped() {
+        let hops = [hop(1_000_000, 2_000_000), hop(3_000_000, 1_000_000)];
+        let limit = SwapLimit::ExactIn {
+            amount_in: 10_000.into(),
+            amount_out_min: 0.into(),
+        };
+
+        let sandwich = max_sandwich(limit, &hops, 1).unwrap();
+        assert!(sandwich.capped);
+        assert_eq!(sandwich.frontrun_in, U256::from(30_000_000));
+    }
+}
@@ -0,0 +1,232 @@
+use ethers::types::U256;
+
+/// Denominator used for both the dex fee (eg: 9970) and token taxes (eg: 500 = 5%)
+pub const FEE_DENOMINATOR: u64 = 10000;
+
+/// Same as the pair contract's `getAmountOut`
+/// Returns `None` where the router would revert (zero input, empty reserves or overflow)
+pub fn get_amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: U256,
+) -> Option<U256> {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return None;
+    }
+
+    let amount_in_with_fee = amount_in.checked_mul(fee)?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+    let denominator = reserve_in
+        .checked_mul(U256::from(FEE_DENOMINATOR))?
+        .checked_add(amount_in_with_fee)?;
+
+    Some(numerator / denominator)
+}
+
+/// Same as the pair contract's `getAmountIn`
+/// Returns `None` where the router would revert (zero output, not enough liquidity or overflow)
+pub fn get_amount_in(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: U256,
+) -> Option<U256> {
+    if amount_out.is_zero() || reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(U256::from(FEE_DENOMINATOR))?;
+    let denominator = (reserve_out - amount_out).checked_mul(fee)?;
+    if denominator.is_zero() {
+        return None;
+    }
+
+    (numerator / denominator).checked_add(U256::one())
+}
+
+/// Amount left after a fee-on-transfer token takes its cut
+pub fn apply_tax(amount: U256, tax: U256) -> Option<U256> {
+    let denominator = U256::from(FEE_DENOMINATOR);
+    if tax >= denominator {
+        return None;
+    }
+
+    Some(amount - amount.checked_mul(tax)? / denominator)
+}
+
+/// Smallest amount to send so that at least `amount` arrives after tax
+pub fn reverse_tax(amount: U256, tax: U256) -> Option<U256> {
+    let denominator = U256::from(FEE_DENOMINATOR);
+    if tax >= denominator {
+        return None;
+    }
+    if tax.is_zero() || amount.is_zero() {
+        return Some(amount);
+    }
+
+    // `apply_tax` rounds the tax down, so `x` delivers ceil(x * remaining / denominator)
+    let remaining = denominator - tax;
+    let numerator = (amount - 1).checked_mul(denominator)?;
+    Some(numerator / remaining + 1)
+}
+
+/// Output received for a swap involving fee-on-transfer tokens
+///
+/// * `tax_in`: taken when we transfer the input token into the pair
+/// * `tax_out`: taken when the pair transfers the output token to us
+pub fn get_amount_out_with_tax(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: U256,
+    tax_in: U256,
+    tax_out: U256,
+) -> Option<U256> {
+    // the pair only sees what arrived after the input tax
+    let received_by_pair = apply_tax(amount_in, tax_in)?;
+    let amount_out = get_amount_out(received_by_pair, reserve_in, reserve_out, fee)?;
+    apply_tax(amount_out, tax_out)
+}
+
+/// Input needed to receive `amount_out` from a swap involving fee-on-transfer tokens
+pub fn get_amount_in_with_tax(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: U256,
+    tax_in: U256,
+    tax_out: U256,
+) -> Option<U256> {
+    let sent_by_pair = reverse_tax(amount_out, tax_out)?;
+    let received_by_pair = get_amount_in(sent_by_pair, reserve_in, reserve_out, fee)?;
+    reverse_tax(received_by_pair, tax_in)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    // (amount_in, reserve_in, reserve_out, fee, expected amount_out)
+    // expected values follow the pair contract formula, see `tests/swap_math.rs` for the
+    // comparison against the deployed router
+    const AMOUNT_OUT_FIXTURES: [(&str, &str, &str, u64, &str); 5] = [
+        (
+            "1000000000000000000",
+            "5000000000000000000000",
+            "10000000000000",
+            9970,
+            "1993602475",
+        ),
+        ("1", "1000", "1000", 9970, "0"),
+        ("1000", "1000", "1000", 9970, "499"),
+        ("123456789", "987654321", "555555555", 9975, "61591182"),
+        (
+            "10000000000000000000000",
+            "5192296858534827628530496329220095",
+            "5192296858534827628530496329220095",
+            9970,
+            "9969999999980856083019",
+        ),
+    ];
+
+    #[test]
+    fn amount_out_matches_fixtures() {
+        for (amount_in, reserve_in, reserve_out, fee, expected) in AMOUNT_OUT_FIXTURES {
+            let out = get_amount_out(u(amount_in), u(reserve_in), u(reserve_out), U256::from(fee));
+            assert_eq!(out, Some(u(expected)), "amount_in {amount_in}");
+        }
+    }
+
+    #[test]
+    fn amount_in_round_trips() {
+        for (_, reserve_in, reserve_out, fee, expected) in AMOUNT_OUT_FIXTURES {
+            let amount_out = u(expected);
+            if amount_out.is_zero() {
+                continue;
+            }
+
+            let fee = U256::from(fee);
+            let amount_in = get_amount_in(amount_out, u(reserve_in), u(reserve_out), fee).unwrap();
+
+            // the minimal input has to give at least the requested output, one less must not
+            let out = get_amount_out(amount_in, u(reserve_in), u(reserve_out), fee).unwrap();
+            assert!(out >= amount_out);
+            let out = get_amount_out(amount_in - 1, u(reserve_in), u(reserve_out), fee).unwrap();
+            assert!(out < amount_out);
+        }
+    }
+
+    #[test]
+    fn reverts_where_router_reverts() {
+        let fee = U256::from(9970);
+        assert_eq!(get_amount_out(U256::zero(), u("10"), u("10"), fee), None);
+        assert_eq!(get_amount_out(u("10"), U256::zero(), u("10"), fee), None);
+        assert_eq!(get_amount_in(u("10"), u("10"), u("10"), fee), None);
+        assert_eq!(get_amount_out(U256::MAX, u("10"), u("10"), fee), None);
+    }
+
+    #[test]
+    fn taxes_are_applied_on_each_side() {
+        let (reserve_in, reserve_out, fee) = (u("1000000000"), u("1000000000"), U256::from(9970));
+        let tax = U256::from(500); // 5%
+
+        let plain = get_amount_out(u("1000000"), reserve_in, reserve_out, fee).unwrap();
+        let taxed_out = get_amount_out_with_tax(
+            u("1000000"),
+            reserve_in,
+            reserve_out,
+            fee,
+            U256::zero(),
+            tax,
+        )
+        .unwrap();
+        assert_eq!(taxed_out, plain - plain * 500 / 10000);
+
+        let taxed_in = get_amount_out_with_tax(
+            u("1000000"),
+            reserve_in,
+            reserve_out,
+            fee,
+            tax,
+            U256::zero(),
+        )
+        .unwrap();
+        assert_eq!(
+            taxed_in,
+            get_amount_out(u("950000"), reserve_in, reserve_out, fee).unwrap()
+        );
+
+        let needed =
+            get_amount_in_with_tax(taxed_in, reserve_in, reserve_out, fee, tax, tax).unwrap();
+        let out = get_amount_out_with_tax(needed, reserve_in, reserve_out, fee, tax, tax).unwrap();
+        assert!(out >= taxed_in);
+    }
+
+    #[test]
+    fn full_tax_is_rejected() {
+        assert_eq!(apply_tax(u("100"), U256::from(FEE_DENOMINATOR)), None);
+        assert_eq!(reverse_tax(u("100"), U256::from(FEE_DENOMINATOR)), None);
+    }
+
+    #[test]
+    fn reverse_tax_is_the_smallest_amount() {
+        let tax = U256::from(500);
+        // 99 - floor(99 * 5%) = 95 already
+        assert_eq!(reverse_tax(u("95"), tax), Some(u("99")));
+        assert_eq!(reverse_tax(U256::zero(), tax), Some(U256::zero()));
+
+        for amount in 1..2000u64 {
+            let amount = U256::from(amount);
+            let sent = reverse_tax(amount, tax).unwrap();
+            assert!(apply_tax(sent, tax).unwrap() >= amount, "{amount}");
+            assert!(apply_tax(sent - 1, tax).unwrap() < amount, "{amount}");
+        }
+    }
+}
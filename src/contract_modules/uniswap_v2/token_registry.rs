@@ -0,0 +1,92 @@
+use ethers::prelude::*;
+use ethers::utils::format_units;
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+use super::data_collector::token_info::get_all_token_info_via_batched_calls;
+use super::types::TokenInfo;
+
+// Assumed when a token has no `decimals()`
+const DEFAULT_DECIMALS: u8 = 18;
+
+/// Symbol, name and decimals of every token seen, kept next to `db.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenRegistry {
+    pub tokens: HashMap<Address, TokenInfo>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
+        let mut file = File::create(file_path)?;
+        let serialized = serde_json::to_string_pretty(self)?;
+        file.write_all(serialized.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_from_file(file_path: &str) -> std::io::Result<TokenRegistry> {
+        let file = File::open(file_path)?;
+        let reader = std::io::BufReader::new(file);
+        let registry: TokenRegistry = serde_json::from_reader(reader)?;
+        Ok(registry)
+    }
+
+    /// Fetches the metadata of the tokens not in the registry yet
+    pub async fn update<M: 'static + Middleware>(
+        &mut self,
+        tokens: impl IntoIterator<Item = Address>,
+        middleware: Arc<M>,
+        progress_bar: ProgressBar,
+    ) {
+        let mut missing: Vec<Address> = tokens
+            .into_iter()
+            .filter(|token| !self.tokens.contains_key(token))
+            .collect();
+        missing.sort();
+        missing.dedup();
+
+        if missing.is_empty() {
+            return;
+        }
+
+        let infos = get_all_token_info_via_batched_calls(&missing, middleware, progress_bar).await;
+        self.tokens.extend(infos);
+    }
+
+    pub fn decimals(&self, token: Address) -> u8 {
+        self.tokens
+            .get(&token)
+            .and_then(|info| info.decimals)
+            .unwrap_or(DEFAULT_DECIMALS)
+    }
+
+    // Symbol, or the address for tokens without one
+    pub fn symbol(&self, token: Address) -> String {
+        match self.tokens.get(&token).and_then(|info| info.symbol.clone()) {
+            Some(symbol) => symbol,
+            None => format!("{:?}", token),
+        }
+    }
+
+    /// `amount` scaled by the token's decimals, eg: "1.5 WETH"
+    pub fn format_amount(&self, token: Address, amount: U256) -> String {
+        let value = format_units(amount, self.decimals(token) as u32).unwrap_or_default();
+        format!("{} {}", value, self.symbol(token))
+    }
+
+    /// eg: "WETH -> USDC -> DAI -> WETH"
+    pub fn format_path(&self, tokens: &[Address]) -> String {
+        tokens
+            .iter()
+            .map(|token| self.symbol(*token))
+            .collect::<Vec<String>>()
+            .join(" -> ")
+    }
+}
@@ -1,5 +1,5 @@
 use ethers::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 // Uniswap V2
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +17,9 @@ pub struct UniV2 {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniV2Pool {
     pub address: Address,
+    // Factory the pool was created by
+    #[serde(default)]
+    pub factory: Address,
 
     pub token0: Address,
     pub token1: Address,
@@ -26,8 +29,89 @@ pub struct UniV2Pool {
 
     // router fee
     pub router_fee: U256,
-    //  token tax when token0 is in
-    pub fees0: U256,
-    //  token tax when token1 is in
-    pub fees1: U256,
+    //  taxes of token0
+    #[serde(deserialize_with = "token_tax_or_sell_tax")]
+    pub fees0: TokenTax,
+    //  taxes of token1
+    #[serde(deserialize_with = "token_tax_or_sell_tax")]
+    pub fees1: TokenTax,
+    // how token0 behaves when traded
+    #[serde(default)]
+    pub safety0: TokenSafety,
+    // how token1 behaves when traded
+    #[serde(default)]
+    pub safety1: TokenSafety,
+}
+
+/// Fee-on-transfer taxes of a single token (out of 10000)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenTax {
+    // taken when the token leaves a pair (we receive it)
+    pub buy: U256,
+    // taken when the token is sent into a pair
+    pub sell: U256,
+    // taken on a plain wallet to wallet transfer
+    pub transfer: U256,
+    // unix time of the last measurement
+    #[serde(default)]
+    pub verified_at: u64,
+    // set once a re-check measured something different than before
+    #[serde(default)]
+    pub changed: bool,
+}
+
+impl TokenTax {
+    // Whether both measured the same taxes, ignoring when
+    pub fn same_taxes(&self, other: &TokenTax) -> bool {
+        self.buy == other.buy && self.sell == other.sell && self.transfer == other.transfer
+    }
+}
+
+// Older checkpoints stored a single tax per token, it's taken as the sell tax
+fn token_tax_or_sell_tax<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TokenTax, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredTax {
+        Taxes(TokenTax),
+        Sell(U256),
+    }
+
+    Ok(match StoredTax::deserialize(deserializer)? {
+        StoredTax::Taxes(tax) => tax,
+        StoredTax::Sell(sell) => TokenTax {
+            sell,
+            ..TokenTax::default()
+        },
+    })
+}
+
+/// How a token behaves when bought and sold back
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenSafety {
+    // not checked yet, not traded until it is
+    #[default]
+    Unchecked,
+    // no tax, trades every time
+    Safe,
+    // constant tax, trades every time
+    Taxed,
+    // trades only some of the time (size limits, cooldowns, changing taxes)
+    Limited,
+    // can't be sold back
+    Honeypot,
+}
+
+impl TokenSafety {
+    // Whether the token can be used in a cycle
+    pub fn is_tradable(&self) -> bool {
+        matches!(self, TokenSafety::Safe | TokenSafety::Taxed)
+    }
+}
+
+/// ERC20 metadata, `None` where the token doesn't implement the call
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    pub decimals: Option<u8>,
 }
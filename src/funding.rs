@@ -0,0 +1,191 @@
+use ethers::abi::parse_abi;
+use ethers::prelude::*;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, RwLock};
+
+use crate::constants::WETH;
+use crate::helpers::address;
+use crate::states::block_state::BlockEvent;
+use crate::wallet::{fetch_wallet, Wallet};
+
+// Flash funded cycles are searched up to this much WETH, 10,000 WETH
+const MAX_FLASH_IN: &str = "10000000000000000000000";
+
+/// Where the WETH traded through a cycle comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Funding {
+    /// Our own WETH
+    Inventory,
+    /// Borrowed from the cycle's first pair, which is repaid in WETH once the cycle went through
+    FlashSwap,
+    /// Borrowed from `provider` and repaid with its fee
+    FlashLoan { provider: Address },
+}
+
+/// A contract lending WETH within a tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashLoanProvider {
+    pub address: Address,
+    // out of 10000
+    pub fee: U256,
+}
+
+impl FlashLoanProvider {
+    /// Fee owed on borrowing `amount`, rounded up
+    pub fn cost(&self, amount: U256) -> U256 {
+        (amount * self.fee + 9999) / 10000
+    }
+}
+
+/// Capital cycles can be sized with
+#[derive(Debug, Clone, Default)]
+pub struct Capital {
+    // WETH we hold, see `track_inventory`
+    pub inventory: U256,
+    pub flash_swaps: bool,
+    pub flash_loans: Vec<FlashLoanProvider>,
+}
+
+impl Capital {
+    /// Flash funding from `FLASH_SWAPS` (on unless "false") and `FLASH_LOANS`
+    /// (`provider:fee,...`, fee out of 10000), the inventory starts empty
+    pub fn from_env() -> Self {
+        let flash_swaps = match std::env::var("FLASH_SWAPS") {
+            Ok(value) => value.parse().expect("invalid FLASH_SWAPS"),
+            Err(_) => true,
+        };
+        let flash_loans = match std::env::var("FLASH_LOANS") {
+            Ok(value) => value
+                .split(',')
+                .filter(|provider| !provider.trim().is_empty())
+                .map(|provider| {
+                    let (provider, fee) = provider
+                        .trim()
+                        .split_once(':')
+                        .expect("invalid FLASH_LOANS");
+                    FlashLoanProvider {
+                        address: provider.parse().expect("invalid FLASH_LOANS"),
+                        fee: U256::from_dec_str(fee).expect("invalid FLASH_LOANS"),
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            inventory: U256::zero(),
+            flash_swaps,
+            flash_loans,
+        }
+    }
+
+    /// Every way of funding a cycle, with the most WETH it provides
+    pub fn options(&self) -> Vec<(Funding, U256)> {
+        let max_flash_in = U256::from_dec_str(MAX_FLASH_IN).unwrap();
+        let mut options = Vec::new();
+
+        if !self.inventory.is_zero() {
+            options.push((Funding::Inventory, self.inventory));
+        }
+        if self.flash_swaps {
+            options.push((Funding::FlashSwap, max_flash_in));
+        }
+        for provider in &self.flash_loans {
+            let funding = Funding::FlashLoan {
+                provider: provider.address,
+            };
+            options.push((funding, max_flash_in));
+        }
+
+        options
+    }
+
+    /// What funding `amount_in` with `funding` costs on top of the cycle
+    ///
+    /// A flash swap is free: the first pair's K check only asks for the WETH its swap would have
+    /// taken, which the cycle's output covers whenever it's profitable
+    pub fn cost(&self, funding: Funding, amount_in: U256) -> U256 {
+        match funding {
+            Funding::Inventory | Funding::FlashSwap => U256::zero(),
+            Funding::FlashLoan { provider } => self
+                .flash_loans
+                .iter()
+                .find(|flash_loan| flash_loan.address == provider)
+                .map(|flash_loan| flash_loan.cost(amount_in))
+                .unwrap_or(U256::MAX),
+        }
+    }
+}
+
+/// `token` balance of `owner` at `block`
+pub async fn token_balance<M: Middleware>(
+    middleware: &Arc<M>,
+    token: Address,
+    owner: Address,
+    block: Option<BlockId>,
+) -> Option<U256> {
+    let contract = BaseContract::from(
+        parse_abi(&["function balanceOf(address) external view returns (uint256)"]).unwrap(),
+    );
+
+    let data = contract.encode("balanceOf", owner).ok()?;
+    let tx = TransactionRequest::new().to(token).data(data);
+    let output = middleware.call(&tx.into(), block).await.ok()?;
+    contract.decode_output("balanceOf", output).ok()
+}
+
+/// WETH balance of `owner` at `block`
+pub async fn weth_balance<M: Middleware>(
+    middleware: &Arc<M>,
+    owner: Address,
+    block: Option<BlockId>,
+) -> Option<U256> {
+    token_balance(middleware, address(WETH), owner, block).await
+}
+
+/// Keeps the inventory at the WETH balance of the wallet, which is reconciled against every
+/// confirmed block along with its other balances and nonce
+pub async fn track_inventory(
+    wallet: Arc<RwLock<Wallet>>,
+    client: Arc<Provider<Ws>>,
+    mut events: broadcast::Receiver<BlockEvent>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(d) => d,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+
+        let (owner, tokens) = {
+            let wallet = wallet.read().await;
+            (
+                wallet.address,
+                wallet.tokens.keys().copied().collect::<Vec<_>>(),
+            )
+        };
+        let snapshot = match fetch_wallet(&client, owner, &tokens, event.number).await {
+            Some(d) => d,
+            None => {
+                warn!("Failed on reading the wallet at block {}", event.number);
+                continue;
+            }
+        };
+
+        let mut wallet = wallet.write().await;
+        let inventory = wallet.weth();
+        wallet.reconcile(snapshot);
+        if wallet.weth() != inventory {
+            debug!(
+                "Block {} | WETH inventory {} -> {}",
+                event.number,
+                inventory,
+                wallet.weth()
+            );
+        }
+    }
+}
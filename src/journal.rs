@@ -0,0 +1,77 @@
+use ethers::prelude::*;
+use log::*;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::calc::CycleHop;
+use crate::contract_modules::uniswap_v2::sandwich::SandwichReport;
+use crate::funding::Funding;
+
+pub const JOURNAL_PATH: &str = "./opportunities.jsonl";
+
+/// Something the bot found, kept for later research
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Backrun {
+        tx: TxHash,
+        block: U64,
+        pools: Vec<Address>,
+        token_path: Vec<Address>,
+        hops: Vec<CycleHop>,
+        profit: String,
+        optimal_in: U256,
+        funding: Funding,
+        funding_cost: U256,
+    },
+    Sandwich(SandwichReport),
+}
+
+#[derive(Serialize)]
+struct JournalRecord<'a> {
+    // unix time
+    recorded_at: u64,
+    #[serde(flatten)]
+    entry: &'a JournalEntry,
+}
+
+/// Append only log of opportunities, one JSON object per line
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn open(file_path: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, entry: &JournalEntry) {
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+
+        let mut line = match serde_json::to_string(&JournalRecord { recorded_at, entry }) {
+            Ok(d) => d,
+            Err(error) => {
+                warn!("Failed on serializing journal entry: {}", error);
+                return;
+            }
+        };
+        line.push('\n');
+
+        if let Err(error) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Failed on writing journal entry: {}", error);
+        }
+    }
+}
@@ -3,59 +3,119 @@ pub mod components;
 pub mod config;
 pub mod constants;
 pub mod contract_modules;
+pub mod funding;
 pub mod helpers;
+pub mod journal;
 pub mod recon;
 pub mod state;
 pub mod states;
+pub mod tax_validator;
+#[cfg(test)]
+pub mod test_utils;
 pub mod updater;
 pub mod utils;
+pub mod wallet;
 
 use config::Config;
-use contract_modules::uniswap_v2::checkpoint::Storage;
-use crossbeam_channel::unbounded;
-use ethers::utils::format_units;
+use contract_modules::uniswap_v2::ban_list::{
+    run_ban_list_command, watch_ban_list, BanList, BAN_LIST_PATH,
+};
+use contract_modules::uniswap_v2::checkpoint::{
+    Storage, BINARY_CHECKPOINT_PATH, JSON_CHECKPOINT_PATH,
+};
+use contract_modules::uniswap_v2::token_registry::TokenRegistry;
+use indicatif::ProgressBar;
 use state::State;
 use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use log::*;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
 use ethers::prelude::*;
 
 use crate::calc::find_optimal_cycles;
 use crate::contract_modules::uniswap_v2::data_collector::data_collector::update_reserves;
 use crate::contract_modules::uniswap_v2::get_uni_v2;
+use crate::contract_modules::uniswap_v2::sandwich::analyze_sandwich;
+use crate::contract_modules::uniswap_v2::types::UniV2Pool;
+use crate::funding::{track_inventory, Capital};
+use crate::journal::{Journal, JournalEntry, JOURNAL_PATH};
+use crate::recon::pending::{fetch_next_nonces, resimulate_if_stale, PendingTracker};
+use crate::recon::queue::{PriorityQueue, QUEUE_CAPACITY};
 use crate::state::StateUpdateInternal;
+use crate::states::block_state::BlockEvent;
+use crate::wallet::{fetch_wallet, PnlEntry, Wallet};
 use contract_modules::uniswap_v2;
 
+// Given to the tasks to stop once shut down, and then again to save the checkpoint
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Token metadata kept between runs
+const TOKENS_PATH: &str = "./tokens.json";
+
+// Set once the tasks stopping on a shutdown are started, a signal before exits right away
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
 pub fn init() {}
 
 // TODO: make code less ugly
 pub async fn run(at_exit: std::sync::mpsc::Receiver<()>) {
     info!("Starting...");
-    tokio::task::spawn(exit(at_exit));
+    let shutdown = CancellationToken::new();
+    {
+        let shutdown = shutdown.clone();
+        std::thread::spawn(move || exit(at_exit, shutdown));
+    }
+
+    if convert_checkpoint() {
+        return;
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    if run_ban_list_command(&args[1..]) {
+        return;
+    }
 
     let config = Config::new().await;
     let uni_v2 = get_uni_v2();
     let load = should_load_data_from_file();
 
-    let mut pairs;
+    let mut storage = match Storage::load() {
+        Ok(storage) => storage,
+        Err(error) => {
+            if load {
+                panic!("Failed on loading data: {}", error);
+            }
+            info!("No checkpoint to continue from ({}), collecting all pairs", error);
+            Storage::new(Vec::new(), U256::zero())
+        }
+    };
 
     if !load {
         let now = Instant::now();
-        pairs = match uniswap_v2::data_collector::data_collector::get_all_pairs(
+        // only pairs created since the checkpoint are collected
+        let collected = match uniswap_v2::data_collector::data_collector::get_all_pairs(
             uni_v2.clone(),
+            &storage,
             config.wss.clone(),
+            &shutdown,
         )
         .await
         {
             Some(d) => d,
             None => return,
         };
+        info!(
+            "New pairs: {:?}, skipped: {:?}",
+            collected.pairs.len(),
+            collected.skipped.len()
+        );
+        storage.merge_pools(collected.pairs);
+        storage.skipped = collected.skipped;
+        storage.factory_lengths.extend(collected.factory_lengths);
         info!("time took for query: {:?}", now.elapsed());
-    } else {
-        let storage = Storage::load_from_file("./db.json").expect("Failed on loading data");
-        pairs = storage.pools;
     }
 
     let block = match config.wss.get_block_number().await {
@@ -66,36 +126,227 @@ pub async fn run(at_exit: std::sync::mpsc::Receiver<()>) {
         }
     };
 
-    update_reserves(&mut pairs, uni_v2.clone(), config.wss.clone()).await;
+    update_reserves(&mut storage.pools, &uni_v2, config.wss.clone()).await;
+    storage.block = block.as_u64().into();
+
+    // reserve deltas of every following block get appended to this snapshot by the updater
+    if let Err(error) = storage.replace_binary(BINARY_CHECKPOINT_PATH) {
+        warn!("Failed on saving checkpoint: {}", error);
+    }
+
+    // the checkpoint keeps every pool, so that changed filters apply on the next start
+    let pairs = config
+        .pool_filter
+        .apply(&storage.pools, config.wss.clone(), block)
+        .await;
 
     info!("Length of pairs: {:?}", pairs.len());
 
-    let state: Arc<Mutex<State>> = Arc::new(Mutex::new(state::State::new_state(&pairs)));
+    // only tokens not seen on a previous run are fetched
+    let mut token_registry = TokenRegistry::load_from_file(TOKENS_PATH).unwrap_or_default();
+    token_registry
+        .update(
+            pairs.iter().flat_map(|pair| [pair.token0, pair.token1]),
+            config.wss.clone(),
+            ProgressBar::hidden(),
+        )
+        .await;
+    if let Err(error) = token_registry.save_to_file(TOKENS_PATH) {
+        warn!("Failed on saving token registry: {}", error);
+    }
 
-    // tokio::task::spawn(run_exit_save(at_exit, state.clone(), config.wss.clone()));
-    let block_oracle = states::block_state::BlockOracle::new(config.wss.clone())
-        .await
-        .expect("Panic at block oracle creation");
+    let ban_list = match BanList::load() {
+        Ok(d) => d,
+        Err(error) => {
+            error!("Failed on loading ban list {}: {}", BAN_LIST_PATH, error);
+            return;
+        }
+    };
+    info!(
+        "Banned tokens: {} pools: {}",
+        ban_list.tokens.len(),
+        ban_list.pools.len()
+    );
+    // written out on the first start so that the seeded list can be edited
+    if !std::path::Path::new(BAN_LIST_PATH).exists() {
+        if let Err(error) = ban_list.save_to_file(BAN_LIST_PATH) {
+            warn!("Failed on saving ban list: {}", error);
+        }
+    }
+    let state: Arc<Mutex<State>> =
+        Arc::new(Mutex::new(state::State::new_state(&pairs, &ban_list)));
+
+    let block_oracle =
+        states::block_state::BlockOracle::new(config.wss.clone(), shutdown.clone())
+            .await
+            .expect("Panic at block oracle creation");
 
-    tokio::task::spawn(updater::start_updater(
+    RUNNING.store(true, Ordering::SeqCst);
+    tokio::task::spawn(log_block_events(block_oracle.read().await.subscribe()));
+
+    let updater = tokio::task::spawn(updater::start_updater(
         Arc::clone(&config.wss),
         state.clone(),
+        block_oracle.clone(),
         block,
+        shutdown.clone(),
     ));
 
+    let tax_validator = tokio::task::spawn(tax_validator::start_tax_validator(
+        Arc::clone(&config.wss),
+        state.clone(),
+        shutdown.clone(),
+    ));
+
+    tokio::task::spawn(watch_ban_list(state.clone()));
+
+    // the inventory cycles are sized with is the wallet's WETH, reconciled on every block
+    let capital = Capital::from_env();
+    let mut wallet = Wallet::from_env(config.wallet.address());
+    let (wallet_events, latest_block) = {
+        let block_oracle = block_oracle.read().await;
+        (block_oracle.subscribe(), block_oracle.latest_block.number)
+    };
+    let tokens: Vec<Address> = wallet.tokens.keys().copied().collect();
+    match fetch_wallet(&config.wss, wallet.address, &tokens, latest_block).await {
+        Some(snapshot) => wallet.reconcile(snapshot),
+        None => warn!("Failed on reading the wallet, no inventory until the next block"),
+    }
+    if !wallet.can_pay_gas() {
+        warn!(
+            "Wallet ETH {} is below {}, cycles are found but can't be sent",
+            wallet.eth, wallet.min_gas_balance
+        );
+    }
+    info!(
+        "Wallet {:?} | ETH: {} WETH: {} nonce: {} | flash swaps: {} flash loan providers: {}",
+        wallet.address,
+        wallet.eth,
+        wallet.weth(),
+        wallet.nonce,
+        capital.flash_swaps,
+        capital.flash_loans.len()
+    );
+    let wallet = Arc::new(RwLock::new(wallet));
+    tokio::task::spawn(track_inventory(wallet.clone(), config.wss.clone(), wallet_events));
+
     // Give time to  sync Uni data
     std::thread::sleep(Duration::from_secs(20));
 
-    let (s,r) = unbounded();
-    recon::mempool::start_recon(state.clone(), config.wss.clone(), block_oracle.clone(), s).await;
+    // every queued tx is checked against the blocks confirmed while it waited
+    let tracker = Arc::new(std::sync::Mutex::new(PendingTracker::default()));
+    let (mut block_events, mut current_block, mut next_timestamp) = {
+        let block_oracle = block_oracle.read().await;
+        (
+            block_oracle.subscribe(),
+            block_oracle.latest_block.number,
+            block_oracle.next_block.timestamp,
+        )
+    };
+
+    // scored by recon, the most valuable tx is evaluated first
+    let queue = Arc::new(PriorityQueue::new(QUEUE_CAPACITY));
+    recon::mempool::start_recon(
+        state.clone(),
+        config.wss.clone(),
+        block_oracle.clone(),
+        tracker.clone(),
+        queue.clone(),
+        uni_v2.clone(),
+        config.node_type,
+        shutdown.clone(),
+    )
+    .await;
+    {
+        let (queue, shutdown) = (queue.clone(), shutdown.clone());
+        tokio::task::spawn(async move {
+            shutdown.cancelled().await;
+            queue.close();
+        });
+    }
     
+    let weth = helpers::address(constants::WETH);
     let decoded = hex::decode(constants::SYNC_TOPIC).unwrap();
     let sync_topic = H256::from_slice(&decoded);
 
+    let analyze_sandwiches = should_analyze_sandwiches();
+    let journal = match Journal::open(JOURNAL_PATH) {
+        Ok(d) => Some(d),
+        Err(error) => {
+            warn!("Opportunities won't be journaled: {}", error);
+            None
+        }
+    };
+
     loop {
-        let data = r.recv().unwrap();
+        // `None` once shut down
+        let data = match queue.pop() {
+            Some(d) => d,
+            None => break,
+        };
+
+        let mut lagged = false;
+        loop {
+            match block_events.try_recv() {
+                Ok(event) => {
+                    tracker
+                        .lock()
+                        .unwrap()
+                        .confirm_block(event.number, &event.next_nonces);
+                    current_block = event.number;
+                    next_timestamp = event.next_block.timestamp;
+                }
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                    warn!("Missed {} block events, fetching the nonces again", missed);
+                    lagged = true;
+                }
+                Err(_) => break,
+            }
+        }
+        // the nonces mined in the missed blocks never reached the tracker
+        if lagged {
+            let senders = tracker.lock().unwrap().senders();
+            let next_nonces = fetch_next_nonces(&senders, &config.wss, current_block).await;
+            tracker
+                .lock()
+                .unwrap()
+                .confirm_block(current_block, &next_nonces);
+        }
+
+        // mined or replaced while queued
+        if !tracker.lock().unwrap().is_live(&data.tx) {
+            continue;
+        }
+        let data = match resimulate_if_stale(
+            data,
+            current_block,
+            next_timestamp,
+            &uni_v2,
+            &state,
+            &config.wss,
+            config.node_type,
+        )
+        .await
+        {
+            Some(d) => d,
+            None => continue,
+        };
 
         let mut state = state.lock().await;
+
+        // research only, measured against the reserves before the victim
+        if analyze_sandwiches {
+            if let Some(report) = analyze_sandwich(&data.tx, &uni_v2, &state) {
+                info!(
+                    "Sandwich of {:?} on {:?} | frontrun in: {} profit: {}",
+                    report.tx, report.pool, report.frontrun_in, report.profit
+                );
+                if let Some(journal) = &journal {
+                    journal.record(&JournalEntry::Sandwich(report));
+                }
+            }
+        }
+
         let mut pending_state_updates = Vec::new();
         let mut affected_pairs = Vec::new();
 
@@ -141,26 +392,67 @@ pub async fn run(at_exit: std::sync::mpsc::Receiver<()>) {
         if pending_state_updates.is_empty() { continue }
         State::apply_state_temp(&mut state, pending_state_updates);
 
-        let cycles = find_optimal_cycles(&state, Some(affected_pairs));
+        let fundable = wallet.read().await.fundable(&capital);
+        let cycles = find_optimal_cycles(&state, Some(affected_pairs), &fundable);
         
         let after: Duration = data.time.elapsed();
         if !cycles.is_empty() {
+            let mut wallet = wallet.write().await;
+            for cycle in &cycles {
+                state.record_opportunity(&cycle.cycle_addresses);
+                // nothing is sent yet, every cycle is simulated only
+                wallet.ledger.record(PnlEntry {
+                    tx: data.tx.hash,
+                    block: data.block,
+                    pools: cycle.cycle_addresses.clone(),
+                    funding: cycle.funding,
+                    amount_in: cycle.optimal_in,
+                    profit: cycle.profit,
+                });
+                if let Some(journal) = &journal {
+                    journal.record(&JournalEntry::Backrun {
+                        tx: data.tx.hash,
+                        block: data.block,
+                        pools: cycle.cycle_addresses.clone(),
+                        token_path: cycle.token_path.clone(),
+                        hops: cycle.hops.clone(),
+                        profit: cycle.profit.to_string(),
+                        optimal_in: cycle.optimal_in,
+                        funding: cycle.funding,
+                        funding_cost: cycle.funding_cost,
+                    });
+                }
+            }
             info!(
                 "                  ------> BackRun Tx Hash {:?}",
                 data.tx.hash()
             );
             info!(
-                "                  ------> Profit: {:.9} ",
-                format_units(cycles[0].profit, "ether").unwrap()
+                "                  ------> Path: {}",
+                token_registry.format_path(&cycles[0].token_path)
+            );
+            info!(
+                "                  ------> Profit: {} ",
+                token_registry.format_amount(weth, cycles[0].profit.into_raw())
             );
             info!(
-                "                  ------> Optimal WETH In: {:.9} ",
-                format_units(cycles[0].optimal_in, "ether").unwrap()
+                "                  ------> Optimal In: {} ",
+                token_registry.format_amount(weth, cycles[0].optimal_in)
+            );
+            info!(
+                "                  ------> Funding: {:?} cost: {} ",
+                cycles[0].funding,
+                token_registry.format_amount(weth, cycles[0].funding_cost)
             );
             info!(
                 "                  ------> E2E time: {:?} ",
                 after
             );
+            info!(
+                "                  ------> Simulated PnL: {} over {} cycles ",
+                token_registry.format_amount(weth, wallet.ledger.simulated.into_raw()),
+                wallet.ledger.simulated_count
+            );
             info!(
                 "             ",
             );
@@ -168,6 +460,50 @@ pub async fn run(at_exit: std::sync::mpsc::Receiver<()>) {
 
         State::reset_temp_state(&mut state);
     }
+
+    // the tax validator may be in the middle of a round, both share the same deadline
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+    let latest = match tokio::time::timeout_at(deadline, updater).await {
+        Ok(Ok(d)) => d,
+        Ok(Err(error)) => {
+            error!("Updater failed: {}", error);
+            return;
+        }
+        Err(_) => {
+            warn!("Updater didn't stop in time, the checkpoint is left as appended");
+            return;
+        }
+    };
+    if tokio::time::timeout_at(deadline, tax_validator).await.is_err() {
+        warn!("Tax validator didn't stop in time, its round is dropped");
+    }
+
+    match tokio::time::timeout(SHUTDOWN_TIMEOUT, flush_checkpoint(state, latest)).await {
+        Ok(Ok(())) => info!("Checkpoint saved at block {}", latest),
+        Ok(Err(error)) => warn!("Failed on saving checkpoint: {}", error),
+        Err(_) => warn!("Saving the checkpoint timed out, the previous one is kept"),
+    }
+}
+
+// Rewrites the checkpoint as a single snapshot at `block`, the last block applied to `state`
+//
+// Every stored pool is kept, the ones in `state` with the taxes validated since the start
+async fn flush_checkpoint(state: Arc<Mutex<State>>, block: U64) -> std::io::Result<()> {
+    let pools: Vec<UniV2Pool> = state
+        .lock()
+        .await
+        .pairs_mapping
+        .values()
+        .map(|pair| pair.borrow().clone())
+        .collect();
+
+    tokio::task::spawn_blocking(move || {
+        let mut storage = Storage::load_from_binary(BINARY_CHECKPOINT_PATH)?;
+        storage.merge_pools(pools);
+        storage.block = block.as_u64().into();
+        storage.replace_binary(BINARY_CHECKPOINT_PATH)
+    })
+    .await?
 }
 
 fn should_load_data_from_file() -> bool {
@@ -176,7 +512,62 @@ fn should_load_data_from_file() -> bool {
     args.iter().any(|arg| arg == "load")
 }
 
-async fn exit(signal_at: std::sync::mpsc::Receiver<()>) {
+// `sandwich-analysis` journals what each victim's slippage would allow a sandwich to take
+fn should_analyze_sandwiches() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+
+    args.iter().any(|arg| arg == "sandwich-analysis")
+}
+
+// `to-binary` / `to-json` convert the checkpoint between both formats and exit
+fn convert_checkpoint() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+
+    let result = if args.iter().any(|arg| arg == "to-binary") {
+        Storage::convert_json_to_binary(JSON_CHECKPOINT_PATH, BINARY_CHECKPOINT_PATH)
+    } else if args.iter().any(|arg| arg == "to-json") {
+        Storage::convert_binary_to_json(BINARY_CHECKPOINT_PATH, JSON_CHECKPOINT_PATH)
+    } else {
+        return false;
+    };
+
+    match result {
+        Ok(()) => info!("Checkpoint converted"),
+        Err(error) => error!("Failed on converting checkpoint: {}", error),
+    }
+    true
+}
+
+// Block metrics, one line per confirmed block
+async fn log_block_events(mut events: broadcast::Receiver<BlockEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => debug!(
+                "Block {} {:?} | base fee: {} next: {} | {} syncs applied",
+                event.number,
+                event.hash,
+                event.base_fee,
+                event.next_block.base_fee,
+                event.syncs.len()
+            ),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Block log fell behind by {} blocks", missed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+// The first signal cancels `shutdown`, a second one exits without waiting
+fn exit(signal_at: std::sync::mpsc::Receiver<()>, shutdown: CancellationToken) {
     signal_at.recv().unwrap();
-    std::process::exit(0);
+    if !RUNNING.load(Ordering::SeqCst) {
+        std::process::exit(0);
+    }
+
+    info!("Shutting down, Ctrl-C again to exit right away");
+    shutdown.cancel();
+
+    let _ = signal_at.recv();
+    std::process::exit(1);
 }
@@ -1,31 +1,68 @@
-use crossbeam_channel::{Sender, TrySendError};
 use ethers::prelude::*;
+use log::*;
+use tokio::sync::broadcast::error::TryRecvError;
 use tokio::sync::{Mutex, RwLock};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::task::spawn;
+use tokio_util::sync::CancellationToken;
+use super::pending::PendingTracker;
+use super::queue::{score_tx, token_prices, PriorityQueue};
+use super::router_sim::pending_logs;
+use crate::config::NodeType;
+use crate::contract_modules::uniswap_v2::types::UniV2;
 use crate::states::block_state::BlockOracle;
-use crate::utils::get_logs;
 use crate::state::State;
 
 pub struct FutureTx {
     pub tx: Transaction,
     pub logs: Vec<CallLogFrame>,
     pub time: Instant,
+    // Block the logs were simulated on
+    pub block: U64,
+    // Evaluation priority, see `score_tx`
+    pub score: f64,
 }
 
+// Blocks between two refreshes of the token prices used for scoring
+const PRICE_REFRESH_BLOCKS: u64 = 100;
+
+/// Simulates pending txs into `queue` until `shutdown` is cancelled
 pub async fn start_recon(
     state: Arc<Mutex<State>>,
     wss: Arc<Provider<Ws>>,
     block_oracle: Arc<RwLock<BlockOracle>>,
-    send_to: Sender<FutureTx>,
+    tracker: Arc<std::sync::Mutex<PendingTracker>>,
+    queue: Arc<PriorityQueue>,
+    dexes: Vec<UniV2>,
+    node_type: NodeType,
+    shutdown: CancellationToken,
 ) {
     spawn(async move {
+        // the oracle's current block, then every confirmed one through its events
+        let (mut events, mut latest_block, mut next_base_fee, mut next_timestamp) = {
+            let block_oracle = block_oracle.read().await;
+            (
+                block_oracle.subscribe(),
+                block_oracle.latest_block.number,
+                block_oracle.next_block.base_fee,
+                block_oracle.next_block.timestamp,
+            )
+        };
+
+        let mut prices = token_prices(&state.lock().await);
+        let mut priced_at = latest_block;
+
         let mut subscription: SubscriptionStream<Ws, TxHash> =
             wss.subscribe_pending_txs().await.expect("WSS gave up");
 
         loop {
-            if let Some(tx_hash) = subscription.next().await {
+            let next = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                next = subscription.next() => next,
+            };
+
+            if let Some(tx_hash) = next {
                 let mut full_tx = match wss.get_transaction(tx_hash).await {
                     Ok(Some(d)) => d,
                     _ => continue,
@@ -41,27 +78,59 @@ pub async fn start_recon(
                     continue;
                 }
 
-                let latest_block;
-                let next_base_fee;
-                {
-                    let block_oracle = block_oracle.read().await;
-                    latest_block = BlockNumber::Number(block_oracle.latest_block.number);
-                    next_base_fee = block_oracle.next_block.base_fee;
+                // catch up on the blocks confirmed since the last tx
+                loop {
+                    match events.try_recv() {
+                        Ok(event) => {
+                            latest_block = event.number;
+                            next_base_fee = event.next_block.base_fee;
+                            next_timestamp = event.next_block.timestamp;
+                        }
+                        Err(TryRecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    }
+                }
+                if latest_block >= priced_at + PRICE_REFRESH_BLOCKS {
+                    prices = token_prices(&state.lock().await);
+                    priced_at = latest_block;
                 }
 
-                if full_tx.max_fee_per_gas.unwrap_or(U256::zero()) < next_base_fee {
+                // legacy txs only carry a gas price
+                let max_fee = full_tx.max_fee_per_gas.or(full_tx.gas_price);
+                if max_fee.unwrap_or(U256::zero()) < next_base_fee {
                     continue;
                 }
 
                 let now = Instant::now();
-                let logs = match get_logs(&wss, &full_tx, latest_block).await {
-                    Some(d) => d,
-                    None => continue,
-                };
+                {
+                    let mut tracker = tracker.lock().unwrap();
+                    if let Some(replaced) = tracker.insert(&full_tx, latest_block) {
+                        debug!("Tx {:?} replaced by {:?}", replaced, full_tx.hash);
+                    }
+                    if !tracker.is_live(&full_tx) {
+                        continue;
+                    }
+                }
 
-                let significant_logs = {
+                // router swaps are computed from state, anything else is traced
+                let logs = match pending_logs(
+                    &full_tx,
+                    &dexes,
+                    &state,
+                    &wss,
+                    node_type,
+                    latest_block,
+                    next_timestamp,
+                )
+                .await
+                {
+                        Some(d) => d,
+                        None => continue,
+                    };
+
+                let (significant_logs, score) = {
                     let state = state.lock().await;
-                    logs.into_iter()
+                    let significant_logs = logs.into_iter()
                         .filter_map(|log| {
                             let origin = log.address?;
                             let ptr = state.address_mapping.get(&origin)?;
@@ -71,21 +140,24 @@ pub async fn start_recon(
                                 None
                             }
                         })
-                        .collect::<Vec<CallLogFrame>>()
+                        .collect::<Vec<CallLogFrame>>();
+                    let score =
+                        score_tx(&full_tx, &significant_logs, &state, &prices, next_base_fee);
+                    (significant_logs, score)
                 };
 
                 if !significant_logs.is_empty() {
-                    match send_to.try_send(FutureTx {
+                    queue.push(FutureTx {
                         tx: full_tx,
                         logs: significant_logs,
                         time: now,
-                    }) {
-                        Ok(_) => (),
-                        Err(TrySendError::Full(_)) => continue,
-                        Err(TrySendError::Disconnected(_)) => break,
-                    }
+                        block: latest_block,
+                        score,
+                    });
                 }
             }
         }
+
+        info!("Recon stopped");
     });
 }
\ No newline at end of file
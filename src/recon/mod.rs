@@ -1 +1,4 @@
-pub mod mempool;
\ No newline at end of file
+pub mod mempool;
+pub mod pending;
+pub mod queue;
+pub mod router_sim;
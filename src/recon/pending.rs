@@ -0,0 +1,205 @@
+use ethers::prelude::*;
+use log::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::mempool::FutureTx;
+use super::router_sim::pending_logs;
+use crate::config::NodeType;
+use crate::contract_modules::uniswap_v2::types::UniV2;
+use crate::state::State;
+
+// A tx simulated this many blocks ago or less is simulated again, older ones are dropped
+const MAX_RESIMULATE_BLOCKS: u64 = 2;
+// Pending txs not mined or replaced within this many blocks are forgotten
+const FORGET_AFTER_BLOCKS: u64 = 50;
+
+struct PendingEntry {
+    hash: TxHash,
+    seen_at: U64,
+}
+
+/// Latest pending tx of every sender and nonce, and the nonces already used on chain
+///
+/// A queued `FutureTx` is only worth evaluating while it is still the tx tracked for its
+/// sender and nonce
+#[derive(Default)]
+pub struct PendingTracker {
+    by_nonce: HashMap<(Address, U256), PendingEntry>,
+    // sender -> (lowest nonce not mined yet, last block with a tx of the sender)
+    next_nonces: HashMap<Address, (U256, U64)>,
+}
+
+impl PendingTracker {
+    /// Tracks `tx` as the latest one of its sender and nonce
+    /// Returns the hash of the tx it replaces, if any
+    pub fn insert(&mut self, tx: &Transaction, block: U64) -> Option<TxHash> {
+        let entry = PendingEntry {
+            hash: tx.hash,
+            seen_at: block,
+        };
+
+        self.by_nonce
+            .insert((tx.from, tx.nonce), entry)
+            .map(|replaced| replaced.hash)
+            .filter(|replaced| *replaced != tx.hash)
+    }
+
+    /// Whether `tx` is neither mined, nor replaced by a tx with the same nonce
+    pub fn is_live(&self, tx: &Transaction) -> bool {
+        if let Some((next_nonce, _)) = self.next_nonces.get(&tx.from) {
+            if tx.nonce < *next_nonce {
+                return false;
+            }
+        }
+
+        match self.by_nonce.get(&(tx.from, tx.nonce)) {
+            Some(entry) => entry.hash == tx.hash,
+            None => false,
+        }
+    }
+
+    /// Senders with a pending tx tracked
+    pub fn senders(&self) -> Vec<Address> {
+        let mut senders: Vec<Address> = self.by_nonce.keys().map(|(sender, _)| *sender).collect();
+        senders.sort();
+        senders.dedup();
+        senders
+    }
+
+    /// Applies the next nonce of every sender that had a tx in block `number`
+    pub fn confirm_block(&mut self, number: U64, next_nonces: &HashMap<Address, U256>) {
+        for (sender, next_nonce) in next_nonces {
+            let entry = self.next_nonces.entry(*sender).or_default();
+            *entry = (entry.0.max(*next_nonce), number);
+        }
+
+        let forget_before = number.saturating_sub(FORGET_AFTER_BLOCKS.into());
+        let tracked = self.by_nonce.len();
+        let next_nonces = &self.next_nonces;
+        self.by_nonce.retain(|(sender, nonce), entry| {
+            let mined = next_nonces
+                .get(sender)
+                .map(|(next_nonce, _)| nonce < next_nonce)
+                .unwrap_or(false);
+            !mined && entry.seen_at >= forget_before
+        });
+        // kept after the sender's txs are gone, a tx with a mined nonce may still come in late
+        self.next_nonces
+            .retain(|_, (_, confirmed_at)| *confirmed_at >= forget_before);
+
+        debug!(
+            "Block {} | {} of {} pending txs still tracked",
+            number,
+            self.by_nonce.len(),
+            tracked
+        );
+    }
+}
+
+/// Next nonce of every sender at `block` read from the node, for when the block events with
+/// them were missed. Senders whose call failed are left out
+pub async fn fetch_next_nonces(
+    senders: &[Address],
+    wss: &Arc<Provider<Ws>>,
+    block: U64,
+) -> HashMap<Address, U256> {
+    let block = Some(BlockNumber::Number(block).into());
+    let nonces = futures::future::join_all(
+        senders
+            .iter()
+            .map(|sender| wss.get_transaction_count(*sender, block)),
+    )
+    .await;
+
+    senders
+        .iter()
+        .zip(nonces)
+        .filter_map(|(sender, nonce)| Some((*sender, nonce.ok()?)))
+        .collect()
+}
+
+/// `future_tx` simulated again when the block moved on since its simulation, `None` when it is
+/// too old or doesn't simulate anymore
+pub async fn resimulate_if_stale(
+    future_tx: FutureTx,
+    current_block: U64,
+    next_timestamp: U256,
+    dexes: &[UniV2],
+    state: &Arc<Mutex<State>>,
+    wss: &Arc<Provider<Ws>>,
+    node_type: NodeType,
+) -> Option<FutureTx> {
+    if future_tx.block >= current_block {
+        return Some(future_tx);
+    }
+    if current_block - future_tx.block > MAX_RESIMULATE_BLOCKS.into() {
+        debug!(
+            "Dropping tx {:?} simulated at block {}",
+            future_tx.tx.hash, future_tx.block
+        );
+        return None;
+    }
+
+    let logs = pending_logs(
+        &future_tx.tx,
+        dexes,
+        state,
+        wss,
+        node_type,
+        current_block,
+        next_timestamp,
+    )
+    .await?;
+    Some(FutureTx {
+        logs,
+        block: current_block,
+        ..future_tx
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(nonce: u64, hash: u64) -> Transaction {
+        Transaction {
+            hash: TxHash::from_low_u64_be(hash),
+            from: Address::from_low_u64_be(1),
+            nonce: nonce.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn late_tx_with_a_mined_nonce_is_not_live() {
+        let mut tracker = PendingTracker::default();
+        let sender = Address::from_low_u64_be(1);
+        tracker.insert(&tx(5, 1), U64::from(100));
+
+        // nonce 5 mined, nothing of the sender is pending anymore
+        tracker.confirm_block(U64::from(101), &HashMap::from([(sender, U256::from(6))]));
+        assert!(!tracker.is_live(&tx(5, 1)));
+
+        // the same nonce seen late in the mempool
+        tracker.insert(&tx(5, 2), U64::from(102));
+        assert!(!tracker.is_live(&tx(5, 2)));
+        tracker.insert(&tx(6, 3), U64::from(102));
+        assert!(tracker.is_live(&tx(6, 3)));
+
+        // forgotten once the sender had no tx for long enough
+        let later = U64::from(101 + FORGET_AFTER_BLOCKS + 1);
+        tracker.confirm_block(later, &HashMap::new());
+        assert!(!tracker.next_nonces.contains_key(&sender));
+    }
+
+    #[test]
+    fn senders_are_listed_once() {
+        let mut tracker = PendingTracker::default();
+        tracker.insert(&tx(5, 1), U64::from(100));
+        tracker.insert(&tx(6, 2), U64::from(100));
+
+        assert_eq!(tracker.senders(), vec![Address::from_low_u64_be(1)]);
+    }
+}
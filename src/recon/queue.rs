@@ -0,0 +1,270 @@
+use ethers::prelude::*;
+use log::*;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Condvar, Mutex};
+
+use super::mempool::FutureTx;
+use crate::constants::SYNC_TOPIC;
+use crate::contract_modules::uniswap_v2::pool_filter::{u256_to_f64, weth_prices};
+use crate::contract_modules::uniswap_v2::types::UniV2Pool;
+use crate::state::State;
+
+// Queued txs kept at most, the lowest scored ones are shed past this
+pub const QUEUE_CAPACITY: usize = 256;
+// Pools with less than this on the priced side don't price a token, 0.5 WETH
+const MIN_PRICING_VALUE: f64 = 5e17;
+
+#[derive(Debug, Clone, Copy)]
+struct QueueKey {
+    score: f64,
+    // keeps equal scores apart, older first
+    sequence: u64,
+}
+
+impl PartialEq for QueueKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueKey {}
+
+impl PartialOrd for QueueKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+struct QueueState {
+    entries: BTreeMap<QueueKey, FutureTx>,
+    sequence: u64,
+    shed: u64,
+    // no more txs are handed out once closed
+    closed: bool,
+}
+
+/// Pending txs waiting for evaluation, highest score first
+///
+/// Full, a new tx pushes out the lowest scored one, so when evaluation falls behind the
+/// dust is what gets skipped
+pub struct PriorityQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl PriorityQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                entries: BTreeMap::new(),
+                sequence: 0,
+                shed: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, future_tx: FutureTx) {
+        let mut state = self.state.lock().unwrap();
+        let key = QueueKey {
+            score: future_tx.score,
+            sequence: state.sequence,
+        };
+        state.sequence += 1;
+        state.entries.insert(key, future_tx);
+
+        if state.entries.len() > self.capacity {
+            if let Some((_, shed)) = state.entries.pop_first() {
+                state.shed += 1;
+                debug!(
+                    "Queue full, shed tx {:?} with score {:.0} | {} shed so far",
+                    shed.tx.hash, shed.score, state.shed
+                );
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    /// Highest scored tx, blocks until there is one, `None` once the queue is closed
+    pub fn pop(&self) -> Option<FutureTx> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some((_, future_tx)) = state.entries.pop_last() {
+                return Some(future_tx);
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Wakes up the consumer, which gets `None` from then on, queued txs are dropped
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Price in wei of one raw unit of every token in `state` that can be priced
+pub fn token_prices(state: &State) -> HashMap<Address, f64> {
+    let pools: Vec<UniV2Pool> = state
+        .pairs_mapping
+        .values()
+        .map(|pair| pair.borrow().clone())
+        .collect();
+
+    weth_prices(&pools, MIN_PRICING_VALUE)
+}
+
+/// Rough value of evaluating `tx`, in wei
+///
+/// Per pool a Sync log moves, the pool's value in WETH times the square of the larger relative
+/// change of its reserves, which is about how the profit of arbing the pool back grows. The sum is
+/// weighted up to 2x by the tip over `next_base_fee`, as higher paying txs land first
+pub fn score_tx(
+    tx: &Transaction,
+    logs: &[CallLogFrame],
+    state: &State,
+    prices: &HashMap<Address, f64>,
+    next_base_fee: U256,
+) -> f64 {
+    let sync_topic = H256::from_slice(&hex::decode(SYNC_TOPIC).unwrap());
+    let mut score = 0.0;
+
+    for log in logs {
+        let (address, topics, data) = match (&log.address, &log.topics, &log.data) {
+            (Some(address), Some(topics), Some(data)) => (address, topics, data),
+            _ => continue,
+        };
+        if !topics.contains(&sync_topic) || data.len() < 64 {
+            continue;
+        }
+
+        let pair = match state
+            .address_mapping
+            .get(address)
+            .and_then(|index| state.pairs_mapping.get(index))
+        {
+            Some(d) => d.borrow(),
+            None => continue,
+        };
+
+        let change = reserve_change(
+            &pair,
+            U256::from_big_endian(&data[0..32]),
+            U256::from_big_endian(&data[32..64]),
+        );
+
+        let value = u256_to_f64(pair.reserve0) * prices.get(&pair.token0).copied().unwrap_or(0.0)
+            + u256_to_f64(pair.reserve1) * prices.get(&pair.token1).copied().unwrap_or(0.0);
+
+        score += value * change * change;
+    }
+
+    let max_fee = tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default();
+    let mut tip = max_fee.saturating_sub(next_base_fee);
+    if let Some(max_priority_fee) = tx.max_priority_fee_per_gas {
+        tip = tip.min(max_priority_fee);
+    }
+    let gas_weight = if next_base_fee.is_zero() {
+        1.0
+    } else {
+        1.0 + (u256_to_f64(tip) / u256_to_f64(next_base_fee)).min(1.0)
+    };
+
+    score * gas_weight
+}
+
+// Larger relative change of the two reserves, a swap moves both but a token with a tax or a
+// rebase can leave one side almost untouched
+fn reserve_change(pair: &UniV2Pool, new_reserve0: U256, new_reserve1: U256) -> f64 {
+    let relative = |old: U256, new: U256| {
+        let old = u256_to_f64(old);
+        if old == 0.0 {
+            return 0.0;
+        }
+        (u256_to_f64(new) - old).abs() / old
+    };
+
+    relative(pair.reserve0, new_reserve0).max(relative(pair.reserve1, new_reserve1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::pool;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    fn future_tx(score: f64) -> FutureTx {
+        FutureTx {
+            tx: Transaction::default(),
+            logs: Vec::new(),
+            time: Instant::now(),
+            block: U64::zero(),
+            score,
+        }
+    }
+
+    #[test]
+    fn closing_wakes_up_the_consumer() {
+        let queue = Arc::new(PriorityQueue::new(QUEUE_CAPACITY));
+        queue.push(future_tx(1.0));
+        queue.push(future_tx(2.0));
+        assert_eq!(queue.pop().unwrap().score, 2.0);
+
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                let mut popped = 0;
+                while queue.pop().is_some() {
+                    popped += 1;
+                }
+                popped
+            })
+        };
+        // the consumer drains the queue and then waits for more until closed
+        while !queue.is_empty() {
+            std::thread::yield_now();
+        }
+        queue.close();
+        assert_eq!(consumer.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn reserve_change_takes_the_side_that_moved_most() {
+        let [token0, token1] = [1, 2].map(Address::from_low_u64_be);
+        let pair = pool(10, token0, token1, U256::from(1000), U256::from(2000));
+
+        // reserve0 barely moves, as with a taxed token0
+        let change = reserve_change(&pair, U256::from(1010), U256::from(3000));
+        assert_eq!(change, 0.5);
+        let change = reserve_change(&pair, U256::from(1100), U256::from(2000));
+        assert_eq!(change, 0.1);
+
+        // an empty side doesn't count
+        let empty = pool(11, token0, token1, U256::zero(), U256::from(2000));
+        assert_eq!(reserve_change(&empty, U256::from(5), U256::from(1000)), 0.5);
+    }
+}
@@ -0,0 +1,89 @@
+use ethers::prelude::*;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::NodeType;
+use crate::contract_modules::uniswap_v2::constants::get_weth_address;
+use crate::contract_modules::uniswap_v2::router_calls::{
+    decode_router_swap, reserves_after, swap_amounts, swap_hops,
+};
+use crate::contract_modules::uniswap_v2::types::UniV2;
+use crate::state::State;
+use crate::utils::{get_logs, get_reserve_logs, sync_log};
+
+/// Sync logs of `tx` in a block at `next_timestamp`, computed from the reserves in `state`
+/// without tracing
+///
+/// `None` when `tx` isn't a swap through one of `dexes` over pools `state` tracks untaxed,
+/// or the router's checks can't be made here, those are left to tracing. A swap past its
+/// deadline or failing its limit reverts, so it has no logs. The sender is taken to hold and
+/// have approved the input, a swap reverting on a transfer is only caught by tracing
+pub fn simulate_router_swap(
+    tx: &Transaction,
+    dexes: &[UniV2],
+    state: &State,
+    next_timestamp: U256,
+) -> Option<Vec<CallLogFrame>> {
+    let swap = decode_router_swap(tx)?;
+    let dex = dexes.iter().find(|dex| dex.router == swap.router)?;
+
+    // without the next block's timestamp the deadline can't be checked
+    if next_timestamp.is_zero() {
+        return None;
+    }
+    if swap.deadline < next_timestamp {
+        return Some(Vec::new());
+    }
+    // an ETH swap reverts unless that end of the path is the router's WETH, a path ending
+    // in another token may be a router on another WETH
+    if !swap.wraps(get_weth_address()) {
+        return None;
+    }
+
+    let hops = swap_hops(&swap, dex, state)?;
+
+    // the router quotes every hop before swapping, a pool visited twice would be off
+    let mut pools = HashSet::new();
+    if !hops.iter().all(|hop| pools.insert(hop.pool)) {
+        return None;
+    }
+
+    let amounts = match swap_amounts(swap.limit, &hops) {
+        Some(d) => d,
+        None => return Some(Vec::new()),
+    };
+
+    let logs = reserves_after(&hops, &amounts)?
+        .into_iter()
+        .map(|(pool, [reserve0, reserve1])| sync_log(pool, reserve0, reserve1))
+        .collect();
+
+    Some(logs)
+}
+
+/// Logs of `tx` on top of `block`, computed locally when possible, traced otherwise
+///
+/// `next_timestamp` is the predicted timestamp of the block after `block`, zero if unknown.
+/// Nodes serving state diffs get the reserves straight from the pairs' storage, which also
+/// covers forks emitting non-standard Sync events
+pub async fn pending_logs(
+    tx: &Transaction,
+    dexes: &[UniV2],
+    state: &Arc<Mutex<State>>,
+    wss: &Arc<Provider<Ws>>,
+    node_type: NodeType,
+    block: U64,
+    next_timestamp: U256,
+) -> Option<Vec<CallLogFrame>> {
+    let local = simulate_router_swap(tx, dexes, &*state.lock().await, next_timestamp);
+    if local.is_some() {
+        return local;
+    }
+
+    if node_type.has_state_diffs() {
+        get_reserve_logs(wss, tx, BlockNumber::Number(block)).await
+    } else {
+        get_logs(wss, tx, BlockNumber::Number(block)).await
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     collections::{HashMap, HashSet},
 };
 
+use crate::contract_modules::uniswap_v2::ban_list::BanList;
 use crate::contract_modules::uniswap_v2::types::UniV2Pool;
 use crate::helpers;
 use crate::constants::WETH;
@@ -39,6 +40,8 @@ pub struct State {
     pub cycles_mapping: HashMap<Address, Vec<Cycle>>,
     // Real state of reserves to re apply after calc
     real_reserve_state: RefCell<HashMap<usize, [U256; 2]>>,
+    /// Pool -> unix time it was last part of a profitable cycle
+    pub opportunities: HashMap<Address, u64>,
 }
 
 // Potential future state update
@@ -51,7 +54,7 @@ pub struct StateUpdateInternal {
 
 impl State {
     /// Initialize state
-    pub fn new_state(pairs: &[UniV2Pool]) -> Self {
+    pub fn new_state(pairs: &[UniV2Pool], ban_list: &BanList) -> Self {
         let mut address_mapping = HashMap::new();
         let mut index_mapping = HashMap::new();
         let mut pairs_mapping = HashMap::new();
@@ -84,7 +87,14 @@ impl State {
                 token1: *address_mapping.get(&pair.token1).unwrap(),
             };
 
-            indexed_pairs.push(indexed_pair);
+            // pools with a limited or honeypot token, or on the ban list, are tracked
+            // but never part of a cycle
+            if pair.safety0.is_tradable()
+                && pair.safety1.is_tradable()
+                && !ban_list.is_banned(pair)
+            {
+                indexed_pairs.push(indexed_pair);
+            }
             pairs_mapping.insert(
                 *address_mapping.get(&pair.address).unwrap(),
                 RefCell::new(pair.clone()),
@@ -126,6 +136,7 @@ impl State {
             pairs_mapping,
             cycles_mapping,
             real_reserve_state,
+            opportunities: HashMap::new(),
         }
     }
 
@@ -204,6 +215,51 @@ impl State {
         }
     }
 
+    /// Drops every cycle going through a banned pool, returns how many were dropped
+    pub fn apply_ban_list(&mut self, ban_list: &BanList) -> usize {
+        self.drop_cycles_through(|pair| ban_list.is_banned(pair))
+    }
+
+    /// Drops every cycle going through a pool with a token no longer tradable, returns how many
+    /// were dropped
+    pub fn drop_untradable_cycles(&mut self) -> usize {
+        self.drop_cycles_through(|pair| !pair.safety0.is_tradable() || !pair.safety1.is_tradable())
+    }
+
+    fn drop_cycles_through<F: Fn(&UniV2Pool) -> bool>(&mut self, is_dropped: F) -> usize {
+        let dropped: HashSet<usize> = self
+            .pairs_mapping
+            .iter()
+            .filter(|(_, pair)| is_dropped(&pair.borrow()))
+            .map(|(index, _)| *index)
+            .collect();
+
+        let mut removed = HashSet::new();
+        for cycles in self.cycles_mapping.values_mut() {
+            cycles.retain(|cycle| {
+                let is_dropped = cycle.iter().any(|pair| dropped.contains(&pair.address));
+                if is_dropped {
+                    removed.insert(cycle.iter().map(|pair| pair.address).collect::<Vec<_>>());
+                }
+                !is_dropped
+            });
+        }
+        self.cycles_mapping.retain(|_, cycles| !cycles.is_empty());
+
+        removed.len()
+    }
+
+    pub fn record_opportunity(&mut self, pools: &[Address]) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+
+        for pool in pools {
+            self.opportunities.insert(*pool, now);
+        }
+    }
+
     pub fn reset_temp_state(state: &mut MutexGuard<State>) {
         for (index, update) in state.real_reserve_state.borrow().iter() {
             let mut pair = match state.pairs_mapping.get(index) {
@@ -217,3 +273,37 @@ impl State {
         state.real_reserve_state.borrow_mut().clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract_modules::uniswap_v2::types::TokenSafety;
+    use crate::test_utils::weth_pool;
+
+    #[test]
+    fn cycles_through_an_untradable_token_are_dropped() {
+        let token = Address::from_low_u64_be(100);
+        let other = Address::from_low_u64_be(101);
+        let pools = [
+            weth_pool(1, token, 1),
+            weth_pool(2, token, 1),
+            weth_pool(3, other, 1),
+            weth_pool(4, other, 1),
+        ];
+        let mut state = State::new_state(&pools, &BanList::default());
+        assert!(state.cycles_mapping.contains_key(&pools[0].address));
+
+        for pair in state.pairs_mapping.values() {
+            let mut pair = pair.borrow_mut();
+            if pair.token0 == token {
+                pair.safety0 = TokenSafety::Honeypot;
+            }
+        }
+
+        assert!(state.drop_untradable_cycles() > 0);
+        assert!(!state.cycles_mapping.contains_key(&pools[0].address));
+        assert!(!state.cycles_mapping.contains_key(&pools[1].address));
+        assert!(state.cycles_mapping.contains_key(&pools[2].address));
+        assert_eq!(state.drop_untradable_cycles(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+use ethers::prelude::*;
+use std::sync::Arc;
+
+use super::block_state::BlockInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Mainnet,
+    Arbitrum,
+    Optimism,
+    Base,
+}
+
+impl Chain {
+    pub fn from_id(chain_id: u64) -> Option<Self> {
+        match chain_id {
+            1 => Some(Chain::Mainnet),
+            42161 => Some(Chain::Arbitrum),
+            10 => Some(Chain::Optimism),
+            8453 => Some(Chain::Base),
+            _ => None,
+        }
+    }
+
+    pub fn policy(&self) -> Arc<dyn BlockPolicy> {
+        match self {
+            Chain::Mainnet => Arc::new(Ethereum),
+            Chain::Arbitrum => Arc::new(Arbitrum::default()),
+            Chain::Optimism | Chain::Base => Arc::new(OpStack::default()),
+        }
+    }
+}
+
+/// Predicts the block after `latest` under the rules of a chain
+pub trait BlockPolicy: std::fmt::Debug + Send + Sync {
+    fn next_block(&self, latest: &Block<TxHash>) -> BlockInfo;
+}
+
+/// Mainnet EIP-1559, 12s slots
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ethereum;
+
+impl BlockPolicy for Ethereum {
+    fn next_block(&self, latest: &Block<TxHash>) -> BlockInfo {
+        BlockInfo::new(
+            latest.number.unwrap_or_default() + 1,
+            latest.timestamp + 12,
+            eip1559_next_base_fee(latest, 2, 8),
+        )
+    }
+}
+
+/// Optimism and Base, EIP-1559 with the Canyon parameters unless the header carries its own
+/// (Holocene and later), 2s blocks
+#[derive(Debug, Clone, Copy)]
+pub struct OpStack {
+    pub block_time: u64,
+    pub elasticity: u64,
+    pub denominator: u64,
+}
+
+impl Default for OpStack {
+    fn default() -> Self {
+        Self {
+            block_time: 2,
+            elasticity: 6,
+            denominator: 250,
+        }
+    }
+}
+
+impl OpStack {
+    // (denominator, elasticity, min base fee) set in `extraData` by the sequencer
+    fn header_params(&self, latest: &Block<TxHash>) -> (u64, u64, U256) {
+        let extra = latest.extra_data.as_ref();
+        let read_u32 = |at: usize| u32::from_be_bytes(extra[at..at + 4].try_into().unwrap()) as u64;
+
+        let (denominator, elasticity, min_base_fee) = match (extra.first(), extra.len()) {
+            // Holocene
+            (Some(0), 9) => (read_u32(1), read_u32(5), U256::zero()),
+            // Jovian adds a minimum base fee
+            (Some(1), 17) => (
+                read_u32(1),
+                read_u32(5),
+                U256::from_big_endian(&extra[9..17]),
+            ),
+            _ => (0, 0, U256::zero()),
+        };
+
+        // zero means the chain still runs on the defaults
+        if denominator == 0 || elasticity == 0 {
+            (self.denominator, self.elasticity, min_base_fee)
+        } else {
+            (denominator, elasticity, min_base_fee)
+        }
+    }
+}
+
+impl BlockPolicy for OpStack {
+    fn next_block(&self, latest: &Block<TxHash>) -> BlockInfo {
+        let (denominator, elasticity, min_base_fee) = self.header_params(latest);
+
+        BlockInfo::new(
+            latest.number.unwrap_or_default() + 1,
+            latest.timestamp + self.block_time,
+            eip1559_next_base_fee(latest, elasticity, denominator).max(min_base_fee),
+        )
+    }
+
+}
+
+/// Arbitrum, the base fee only moves under congestion and blocks come several per second
+#[derive(Debug, Clone, Copy)]
+pub struct Arbitrum {
+    pub min_base_fee: U256,
+}
+
+impl Default for Arbitrum {
+    fn default() -> Self {
+        Self {
+            // 0.01 gwei
+            min_base_fee: U256::from(10_000_000),
+        }
+    }
+}
+
+impl BlockPolicy for Arbitrum {
+    fn next_block(&self, latest: &Block<TxHash>) -> BlockInfo {
+        BlockInfo::new(
+            latest.number.unwrap_or_default() + 1,
+            // timestamps have second precision, the next block most likely shares it
+            latest.timestamp,
+            latest
+                .base_fee_per_gas
+                .unwrap_or_default()
+                .max(self.min_base_fee),
+        )
+    }
+
+}
+
+/// Base fee of the block after `block` under EIP-1559 with the given parameters
+pub fn eip1559_next_base_fee(block: &Block<TxHash>, elasticity: u64, denominator: u64) -> U256 {
+    let base_fee = block.base_fee_per_gas.unwrap_or_default();
+    let gas_target = block.gas_limit / elasticity;
+
+    if gas_target.is_zero() || block.gas_used == gas_target {
+        base_fee
+    } else if block.gas_used > gas_target {
+        let delta = base_fee * (block.gas_used - gas_target) / gas_target / denominator;
+        base_fee + delta.max(U256::one())
+    } else {
+        let delta = base_fee * (gas_target - block.gas_used) / gas_target / denominator;
+        base_fee.saturating_sub(delta)
+    }
+}
+
+// Mainnet is checked against a real header, the rollups against headers recorded in
+// tests/block_policy.rs, those below cover the formulas case by case
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(
+        number: u64,
+        timestamp: u64,
+        gas_limit: u64,
+        gas_used: u64,
+        base_fee: u64,
+        extra_data: &str,
+    ) -> Block<TxHash> {
+        Block {
+            number: Some(number.into()),
+            timestamp: timestamp.into(),
+            gas_limit: gas_limit.into(),
+            gas_used: gas_used.into(),
+            base_fee_per_gas: Some(base_fee.into()),
+            extra_data: extra_data.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mainnet_follows_london_header() {
+        // block 12965000, the first with a base fee
+        let london = header(
+            12965000,
+            1628166822,
+            30029122,
+            30025257,
+            1_000_000_000,
+            "0x",
+        );
+        let next = Ethereum.next_block(&london);
+
+        assert_eq!(next.number, U64::from(12965001));
+        assert_eq!(next.timestamp, U256::from(1628166834u64));
+        assert_eq!(next.base_fee, U256::from(1_124_967_822u64));
+    }
+
+    #[test]
+    fn mainnet_base_fee_bounds() {
+        let target = header(1, 0, 30_000_000, 15_000_000, 1_000_000_000, "0x");
+        let empty = header(1, 0, 30_000_000, 0, 1_000_000_000, "0x");
+        let full = header(1, 0, 30_000_000, 30_000_000, 1_000_000_000, "0x");
+        // the increase is at least 1 wei
+        let tiny = header(1, 0, 30_000_000, 15_000_001, 7, "0x");
+
+        assert_eq!(
+            Ethereum.next_block(&target).base_fee,
+            U256::from(1_000_000_000)
+        );
+        assert_eq!(
+            Ethereum.next_block(&empty).base_fee,
+            U256::from(875_000_000)
+        );
+        assert_eq!(
+            Ethereum.next_block(&full).base_fee,
+            U256::from(1_125_000_000)
+        );
+        assert_eq!(Ethereum.next_block(&tiny).base_fee, U256::from(8));
+    }
+
+    #[test]
+    fn op_stack_uses_canyon_params_without_extra_data() {
+        // gas target 5M, full block moves the base fee by 5 / 250 = 2%
+        let latest = header(100, 1_700_000_000, 30_000_000, 30_000_000, 1_000_000, "0x");
+        let next = OpStack::default().next_block(&latest);
+
+        assert_eq!(next.number, U64::from(101));
+        assert_eq!(next.timestamp, U256::from(1_700_000_002u64));
+        assert_eq!(next.base_fee, U256::from(1_020_000));
+    }
+
+    #[test]
+    fn op_stack_reads_holocene_and_jovian_extra_data() {
+        // denominator 50, elasticity 2: target 15M, empty block drops the base fee by 1/50
+        let holocene = header(1, 0, 30_000_000, 0, 1_000_000, "0x000000003200000002");
+        assert_eq!(
+            OpStack::default().next_block(&holocene).base_fee,
+            U256::from(980_000)
+        );
+
+        // same params with a 990_000 minimum
+        let jovian = header(
+            1,
+            0,
+            30_000_000,
+            0,
+            1_000_000,
+            "0x01000000320000000200000000000f1b30",
+        );
+        assert_eq!(
+            OpStack::default().next_block(&jovian).base_fee,
+            U256::from(990_000)
+        );
+
+        // zeroed params fall back to the defaults
+        let zeroed = header(
+            1,
+            0,
+            30_000_000,
+            30_000_000,
+            1_000_000,
+            "0x000000000000000000",
+        );
+        assert_eq!(
+            OpStack::default().next_block(&zeroed).base_fee,
+            U256::from(1_020_000)
+        );
+    }
+
+    #[test]
+    fn arbitrum_keeps_base_fee_and_timestamp() {
+        let latest = header(200, 1_700_000_000, 1 << 50, 1_000_000, 20_000_000, "0x");
+        let next = Arbitrum::default().next_block(&latest);
+
+        assert_eq!(next.number, U64::from(201));
+        assert_eq!(next.timestamp, U256::from(1_700_000_000u64));
+        assert_eq!(next.base_fee, U256::from(20_000_000));
+
+        let below_min = header(200, 0, 1 << 50, 0, 1, "0x");
+        assert_eq!(
+            Arbitrum::default().next_block(&below_min).base_fee,
+            U256::from(10_000_000)
+        );
+    }
+
+}
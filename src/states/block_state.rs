@@ -1,7 +1,16 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use ethers::prelude::*;
-use tokio::sync::RwLock;
+use log::*;
+use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use super::block_policy::{BlockPolicy, Chain};
+use crate::contract_modules::uniswap_v2::checkpoint::ReserveDelta;
+
+// Events a subscriber may fall behind by before it starts missing them
+const EVENTS_CAPACITY: usize = 64;
 
 #[derive(Debug, Clone, Default)]
 pub struct BlockInfo {
@@ -20,55 +29,42 @@ impl BlockInfo {
         }
     }
 
-    // Find the next block ahead of `prev_block`
-    pub fn find_next_block_info(prev_block: Block<TxHash>) -> Self {
-        let number = prev_block.number.unwrap_or_default() + 1;
-        let timestamp = prev_block.timestamp + 12;
-        let base_fee = Self::calculate_next_block_base_fee(prev_block);
-
-        Self {
-            number,
-            timestamp,
-            base_fee,
-        }
-    }
-
-    pub fn calculate_next_block_base_fee(block: Block<TxHash>) -> U256 {
-        // Get the block base fee per gas
-        let current_base_fee_per_gas = block.base_fee_per_gas.unwrap_or_default();
-
-        // Get the mount of gas used in the block
-        let current_gas_used = block.gas_used;
-
-        let current_gas_target = block.gas_limit / 2;
-
-        if current_gas_used == current_gas_target {
-            current_base_fee_per_gas
-        } else if current_gas_used > current_gas_target {
-            let gas_used_delta = current_gas_used - current_gas_target;
-            let base_fee_per_gas_delta =
-                current_base_fee_per_gas * gas_used_delta / current_gas_target / 8;
-
-            return current_base_fee_per_gas + base_fee_per_gas_delta;
-        } else {
-            let gas_used_delta = current_gas_target - current_gas_used;
-            let base_fee_per_gas_delta =
-                current_base_fee_per_gas * gas_used_delta / current_gas_target / 8;
+}
 
-            return current_base_fee_per_gas - base_fee_per_gas_delta;
-        }
-    }
+/// Published once per new head, after its Sync logs were applied to `State`
+#[derive(Debug, Clone)]
+pub struct BlockEvent {
+    pub number: U64,
+    pub hash: H256,
+    pub timestamp: U256,
+    pub base_fee: U256,
+    pub next_block: BlockInfo,
+    /// Reserves set by the block's Sync logs, in log order
+    pub syncs: Arc<Vec<ReserveDelta>>,
+    /// Next nonce of every sender with a tx in the block
+    pub next_nonces: Arc<HashMap<Address, U256>>,
 }
 
+/// Latest and predicted next block, and the bus every new head is fanned out on
+///
+/// The updater owns the only new heads subscription, it applies the Sync logs of a head and
+/// then publishes it through `BlockOracle::publish`
 #[derive(Debug, Clone)]
 pub struct BlockOracle {
     pub latest_block: BlockInfo,
     pub next_block: BlockInfo,
+    pub chain: Chain,
+    policy: Arc<dyn BlockPolicy>,
+    // dropped on shutdown, closing every subscription
+    events: Option<broadcast::Sender<BlockEvent>>,
 }
 
 impl BlockOracle {
-    // Create new latest block oracle
-    pub async fn new(client: Arc<Provider<Ws>>) -> Result<Arc<RwLock<Self>>, ProviderError> {
+    // Create new latest block oracle, publishing until `shutdown` is cancelled
+    pub async fn new(
+        client: Arc<Provider<Ws>>,
+        shutdown: CancellationToken,
+    ) -> Result<Arc<RwLock<Self>>, ProviderError> {
         let latest_block = match client.get_block(BlockNumber::Latest).await {
             Ok(b) => b,
             Err(e) => return Err(e),
@@ -80,68 +76,86 @@ impl BlockOracle {
             return Err(ProviderError::CustomError("Block not found".to_string()));
         };
 
-        // latets block info
-        let number = lb.number.unwrap();
-        let timestamp = lb.timestamp;
-        let base_fee = lb.base_fee_per_gas.unwrap_or_default();
+        let chain_id = client.get_chainid().await?.as_u64();
+        let chain = Chain::from_id(chain_id).unwrap_or_else(|| {
+            warn!(
+                "No block policy for chain {}, using mainnet rules",
+                chain_id
+            );
+            Chain::Mainnet
+        });
+        let policy = chain.policy();
 
-        let latest_block = BlockInfo::new(number, timestamp, base_fee);
+        // latets block info
+        let latest_block = BlockInfo::new(
+            lb.number.unwrap(),
+            lb.timestamp,
+            lb.base_fee_per_gas.unwrap_or_default(),
+        );
 
         // next block info
-        let number = number + 1;
-        let timestamp = timestamp + 12;
-        let base_fee = BlockInfo::calculate_next_block_base_fee(lb);
-
-        let next_block = BlockInfo::new(number, timestamp, base_fee);
+        let next_block = policy.next_block(&lb);
 
         let oracle = Arc::new(RwLock::new(BlockOracle {
             latest_block,
             next_block,
+            chain,
+            policy,
+            events: Some(broadcast::channel(EVENTS_CAPACITY).0),
         }));
 
-        Self::start(oracle.clone(), client.clone()).await;
-
-        Ok(oracle.clone())
-    }
-
-    async fn start(oracle: Arc<RwLock<BlockOracle>>, client: Arc<Provider<Ws>>) {
-        tokio::task::spawn(async move {
-            // loop so we can reconnect if the websocket connection is lost
-            loop {
-                let mut block_stream = if let Ok(stream) = client.subscribe_blocks().await {
-                    stream
-                } else {
-                    panic!("Failed to create new block stream");
-                };
-
-                while let Some(block) = block_stream.next().await {
-                    // lock the RwLock for write access and update the variable
-                    {
-                        let mut lock = oracle.write().await;
-                        lock.update_block_number(block.number.unwrap());
-                        lock.update_block_timestamp(block.timestamp);
-                        lock.update_base_fee(block);
-                    } // remove write lock due to being out of scope here
-                }
-            }
+        // subscribers see their channel closed and stop with the bot
+        let closing = oracle.clone();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            closing.write().await.events = None;
         });
+
+        Ok(oracle)
     }
 
-    // Updates block's number
-    fn update_block_number(&mut self, block_number: U64) {
-        self.latest_block.number = block_number;
-        self.next_block.number = block_number + 1;
+    /// Receives every block published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<BlockEvent> {
+        match &self.events {
+            Some(events) => events.subscribe(),
+            // already closed
+            None => broadcast::channel(1).1,
+        }
     }
 
-    // Updates block's timestamp
-    fn update_block_timestamp(&mut self, timestamp: U256) {
-        self.latest_block.timestamp = timestamp;
-        self.next_block.timestamp = timestamp + 12;
+    /// Records `head` as the latest block and sends it to all subscribers with the reserves
+    /// its Sync logs set and the nonces its txs used
+    pub async fn publish(
+        oracle: &Arc<RwLock<BlockOracle>>,
+        head: &Block<TxHash>,
+        syncs: Vec<ReserveDelta>,
+        next_nonces: HashMap<Address, U256>,
+    ) {
+        let mut lock = oracle.write().await;
+        lock.update_block(head);
+
+        let event = BlockEvent {
+            number: lock.latest_block.number,
+            hash: head.hash.unwrap_or_default(),
+            timestamp: lock.latest_block.timestamp,
+            base_fee: lock.latest_block.base_fee,
+            next_block: lock.next_block.clone(),
+            syncs: Arc::new(syncs),
+            next_nonces: Arc::new(next_nonces),
+        };
+        // an error only means nobody is subscribed
+        if let Some(events) = &lock.events {
+            let _ = events.send(event);
+        }
     }
 
-    // Updates block's base fee
-    fn update_base_fee(&mut self, latest_block: Block<TxHash>) {
-        self.latest_block.base_fee = latest_block.base_fee_per_gas.unwrap_or_default();
-        self.next_block.base_fee = BlockInfo::calculate_next_block_base_fee(latest_block);
+    // Updates the latest block and the prediction of the next one
+    fn update_block(&mut self, latest_block: &Block<TxHash>) {
+        self.latest_block = BlockInfo::new(
+            latest_block.number.unwrap(),
+            latest_block.timestamp,
+            latest_block.base_fee_per_gas.unwrap_or_default(),
+        );
+        self.next_block = self.policy.next_block(latest_block);
     }
 }
@@ -1 +1,2 @@
+pub mod block_policy;
 pub mod block_state;
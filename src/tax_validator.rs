@@ -0,0 +1,210 @@
+use ethers::prelude::*;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::*;
+use revm::db::{CacheDB, EmptyDB};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    components::simulator::{fork_factory::ForkFactory, slot_finder::SlotFinder},
+    contract_modules::uniswap_v2::{
+        data_collector::{
+            data_collector::{get_token_taxes, pick_token_checks},
+            tax_checker::inject_tax_checker_code,
+        },
+        types::{TokenSafety, TokenTax, UniV2Pool},
+    },
+    state::State,
+};
+
+// Time between two rounds of checks
+const VALIDATION_INTERVAL: Duration = Duration::from_secs(600);
+// Checking is slow, tokens over this wait for the next round
+const TOKENS_PER_ROUND: usize = 200;
+// Pools in an opportunity within this many seconds are checked first
+const RECENT_OPPORTUNITY: u64 = 3600;
+
+/// Re-runs the tax checker on the tokens in `state` against the current block, until
+/// `shutdown` is cancelled
+///
+/// A round in progress stops with the forks' backend and is dropped, checks failing on the
+/// stopped backend would look like honeypots
+pub async fn start_tax_validator(
+    ws_provider: Arc<Provider<Ws>>,
+    state: Arc<Mutex<State>>,
+    shutdown: CancellationToken,
+) {
+    info!("Tax validator started");
+    // the storage layout of a token doesn't change, so slots are kept between rounds
+    let mut slot_finder = SlotFinder::new();
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(VALIDATION_INTERVAL) => {}
+        }
+        validate_taxes(
+            ws_provider.clone(),
+            state.clone(),
+            &mut slot_finder,
+            &shutdown,
+        )
+        .await;
+    }
+    info!("Tax validator stopped");
+}
+
+async fn validate_taxes(
+    ws_provider: Arc<Provider<Ws>>,
+    state: Arc<Mutex<State>>,
+    slot_finder: &mut SlotFinder,
+    shutdown: &CancellationToken,
+) {
+    let block = match ws_provider.get_block_number().await {
+        Ok(d) => d,
+        Err(error) => {
+            error!("An error occurred: {}", error);
+            return;
+        }
+    };
+
+    let checks = {
+        let state = state.lock().await;
+        let pools: Vec<UniV2Pool> = state
+            .pairs_mapping
+            .values()
+            .map(|pair| pair.borrow().clone())
+            .collect();
+
+        // token -> last time one of its pools was in an opportunity
+        let now = unix_time();
+        let mut recent: HashMap<Address, u64> = HashMap::new();
+        for pool in &pools {
+            let seen = match state.opportunities.get(&pool.address) {
+                Some(seen) if now.saturating_sub(*seen) <= RECENT_OPPORTUNITY => *seen,
+                _ => continue,
+            };
+            for token in [pool.token0, pool.token1] {
+                let entry = recent.entry(token).or_default();
+                *entry = (*entry).max(seen);
+            }
+        }
+
+        // recent opportunities first, then the taxes verified the longest ago
+        let mut checks: Vec<_> = pick_token_checks(&pools).into_iter().collect();
+        checks.sort_by_key(|(token, (_, pool))| {
+            let verified_at = if pool.token0 == *token {
+                pool.fees0.verified_at
+            } else {
+                pool.fees1.verified_at
+            };
+            (
+                Reverse(recent.get(token).copied().unwrap_or(0)),
+                verified_at,
+            )
+        });
+        checks.truncate(TOKENS_PER_ROUND);
+        checks.into_iter().collect::<HashMap<_, _>>()
+    };
+    let checked: Vec<Address> = checks.keys().copied().collect();
+
+    // the backend stops with the round, even if a check left behind still holds a fork, or on
+    // shutdown
+    let backend_shutdown = shutdown.child_token();
+    let _backend_shutdown = backend_shutdown.clone().drop_guard();
+    let mut fork_factory = ForkFactory::new_sandbox_factory(
+        ws_provider.clone(),
+        CacheDB::new(EmptyDB::default()),
+        Some(block.into()),
+        backend_shutdown,
+    );
+    inject_tax_checker_code(&mut fork_factory);
+
+    let hidden_progress_bar = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+    let results = get_token_taxes(
+        checks,
+        &mut fork_factory,
+        slot_finder,
+        block,
+        &hidden_progress_bar,
+    )
+    .await;
+    if shutdown.is_cancelled() {
+        info!("Tax validation round dropped on shutdown");
+        return;
+    }
+
+    let mut state = state.lock().await;
+    let mut changed_tokens = HashSet::new();
+    for pair in state.pairs_mapping.values() {
+        let mut pair = pair.borrow_mut();
+        let pair = &mut *pair;
+
+        if let Some((tax, safety)) = results.get(&pair.token0) {
+            if update_tax(&mut pair.fees0, &mut pair.safety0, tax, *safety) {
+                changed_tokens.insert(pair.token0);
+            }
+        }
+        if let Some((tax, safety)) = results.get(&pair.token1) {
+            if update_tax(&mut pair.fees1, &mut pair.safety1, tax, *safety) {
+                changed_tokens.insert(pair.token1);
+            }
+        }
+    }
+
+    for token in &changed_tokens {
+        let (tax, _) = results[token];
+        warn!(
+            "Tax of {:?} changed to buy: {} sell: {} transfer: {}",
+            token, tax.buy, tax.sell, tax.transfer
+        );
+    }
+    // a token turned limited or honeypot is no longer traded
+    let dropped = state.drop_untradable_cycles();
+    if dropped > 0 {
+        warn!(
+            "Dropped {} cycles through tokens no longer tradable",
+            dropped
+        );
+    }
+    for token in checked.iter().filter(|token| !results.contains_key(token)) {
+        warn!("Tax of {:?} could not be checked again", token);
+    }
+
+    info!(
+        "Re-validated taxes of {} tokens at block {} | {} changed",
+        checked.len(),
+        block,
+        changed_tokens.len()
+    );
+}
+
+// Stores a new measurement, returns whether the taxes differ from the stored ones
+fn update_tax(
+    current: &mut TokenTax,
+    current_safety: &mut TokenSafety,
+    measured: &TokenTax,
+    measured_safety: TokenSafety,
+) -> bool {
+    let changed = !current.same_taxes(measured);
+    *current = TokenTax {
+        changed: current.changed || changed,
+        ..*measured
+    };
+    *current_safety = measured_safety;
+
+    changed
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
@@ -0,0 +1,42 @@
+use ethers::prelude::*;
+
+use crate::constants::WETH;
+use crate::contract_modules::uniswap_v2::types::{TokenSafety, TokenTax, UniV2Pool};
+
+/// Untaxed pool with the usual 0.3% fee, both tokens checked safe
+pub fn pool(
+    address: u64,
+    token0: Address,
+    token1: Address,
+    reserve0: U256,
+    reserve1: U256,
+) -> UniV2Pool {
+    UniV2Pool {
+        address: Address::from_low_u64_be(address),
+        factory: Address::zero(),
+        token0,
+        token1,
+        reserve0,
+        reserve1,
+        router_fee: U256::from(9970),
+        fees0: TokenTax::default(),
+        fees1: TokenTax::default(),
+        safety0: TokenSafety::Safe,
+        safety1: TokenSafety::Safe,
+    }
+}
+
+/// `token` against 100 WETH at `tokens_per_weth`, on a factory of its own
+pub fn weth_pool(address: u64, token: Address, tokens_per_weth: u64) -> UniV2Pool {
+    let reserve_weth = U256::exp10(20);
+    UniV2Pool {
+        factory: Address::from_low_u64_be(address),
+        ..pool(
+            address,
+            token,
+            crate::helpers::address(WETH),
+            reserve_weth * tokens_per_weth,
+            reserve_weth,
+        )
+    }
+}
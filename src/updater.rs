@@ -1,28 +1,48 @@
 use ethers::prelude::*;
 use hex;
 use log::*;
-use std::{sync::Arc, time::Instant};
-use tokio::sync::Mutex;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
 
-use crate::{constants::SYNC_TOPIC, state::State};
+use crate::{
+    constants::SYNC_TOPIC,
+    contract_modules::uniswap_v2::checkpoint::{ReserveDelta, Storage, BINARY_CHECKPOINT_PATH},
+    state::State,
+    states::block_state::BlockOracle,
+};
 
-pub async fn start_updater(ws_provider: Arc<Provider<Ws>>, state: Arc<Mutex<State>>, from: U64) {
+/// Keeps `state` up to date from block `from` on until `shutdown` is cancelled
+///
+/// Returns the last block applied to `state`, the one a checkpoint of it continues from
+pub async fn start_updater(
+    ws_provider: Arc<Provider<Ws>>,
+    state: Arc<Mutex<State>>,
+    block_oracle: Arc<RwLock<BlockOracle>>,
+    from: U64,
+    shutdown: CancellationToken,
+) -> U64 {
     let now = Instant::now();
 
     let decoded = hex::decode(SYNC_TOPIC).unwrap();
     let sync_topic = H256::from_slice(&decoded);
 
     let mut from = from;
+    let mut latest = from;
     let block = match ws_provider.get_block_number().await {
         Ok(d) => d,
         Err(error) => {
             error!("An error occurred: {}", error);
-            return;
+            return latest;
         }
     };
 
     while from < block {
-        update_block(ws_provider.clone(), state.clone(), from, sync_topic).await;
+        if shutdown.is_cancelled() {
+            return latest;
+        }
+        update_block(ws_provider.clone(), state.clone(), from.into(), sync_topic).await;
+        latest = from;
         from += U64::one();
     }
 
@@ -30,74 +50,124 @@ pub async fn start_updater(ws_provider: Arc<Provider<Ws>>, state: Arc<Mutex<Stat
         "State updates from bot sync completed | Took: {:?}",
         now.elapsed()
     );
-    loop_blocks(ws_provider, state, sync_topic).await;
+    loop_blocks(ws_provider, state, block_oracle, sync_topic, shutdown)
+        .await
+        .unwrap_or(latest)
 }
 
+/// The bot's only new heads subscription, every head is applied to `state` and then
+/// published on the block oracle
+///
+/// A head being applied when `shutdown` is cancelled is finished, returns the last one
 pub async fn loop_blocks(
     ws_provider: Arc<Provider<Ws>>,
     state: Arc<Mutex<State>>,
+    block_oracle: Arc<RwLock<BlockOracle>>,
     sync_topic: H256,
-) {
+    shutdown: CancellationToken,
+) -> Option<U64> {
     info!("Block updater started");
-    let mut subscription = ws_provider.subscribe_blocks().await.unwrap();
+    let mut latest = None;
+    // loop so we can reconnect if the websocket connection is lost
     loop {
-        if let Some(block) = subscription.next().await {
-            update_block(
-                ws_provider.clone(),
-                state.clone(),
-                block.number.unwrap(),
-                sync_topic,
-            )
-            .await;
-            // info!("------{:?}------",block.number);
+        let mut subscription = match ws_provider.subscribe_blocks().await {
+            Ok(d) => d,
+            Err(error) => panic!("Failed to create new block stream: {}", error),
+        };
+
+        loop {
+            let head = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    info!("Block updater stopped");
+                    return latest;
+                }
+                head = subscription.next() => match head {
+                    Some(d) => d,
+                    None => break,
+                },
+            };
+
+            let block_id = match head.hash {
+                Some(hash) => hash.into(),
+                None => head.number.unwrap_or_default().into(),
+            };
+            let (syncs, next_nonces) =
+                update_block(ws_provider.clone(), state.clone(), block_id, sync_topic).await;
+
+            BlockOracle::publish(&block_oracle, &head, syncs, next_nonces).await;
+            latest = head.number.or(latest);
         }
     }
 }
 
+// Applies the Sync logs of `block` to `state`, returns the reserves they set and the next
+// nonce of every sender in the block
 async fn update_block(
     ws_provider: Arc<Provider<Ws>>,
     state: Arc<Mutex<State>>,
-    block: U64,
+    block: BlockId,
     sync_topic: H256,
-) {
-    let block = match ws_provider.get_block(block).await {
+) -> (Vec<ReserveDelta>, HashMap<Address, U256>) {
+    let block = match ws_provider.get_block_with_txs(block).await {
         Ok(Some(d)) => d,
-        Ok(None) => return,
+        Ok(None) => return Default::default(),
         Err(error) => {
             println!("An error occurred: {}", error);
-            return;
+            return Default::default();
         }
     };
-    let txes = block.transactions;
+    let number = block.number.unwrap_or_default();
+    let mut next_nonces: HashMap<Address, U256> = HashMap::new();
+    for tx in &block.transactions {
+        let next_nonce = next_nonces.entry(tx.from).or_default();
+        *next_nonce = (*next_nonce).max(tx.nonce + 1);
+    }
 
-    for tx in txes {
-        let tx_receipt = match ws_provider.get_transaction_receipt(tx).await {
-            Ok(tx) => tx,
-            Err(_) => continue,
-        };
-        if let Some(full_tx) = tx_receipt {
-            let state_unlocked: tokio::sync::MutexGuard<State> = state.lock().await;
-
-            let logs = full_tx.logs;
-            for log in logs {
-                let pointer = match state_unlocked.address_mapping.get(&log.address) {
-                    Some(d) => *d,
-                    None => continue,
-                };
-
-                for topic in log.topics {
-                    if topic == sync_topic {
-                        let mut pair = match state_unlocked.pairs_mapping.get(&pointer) {
-                            Some(p) => p.borrow_mut(),
-                            None => continue,
-                        };
-
-                        pair.reserve0 = U256::from_big_endian(&log.data[0..32]);
-                        pair.reserve1 = U256::from_big_endian(&log.data[32..]);
-                    }
-                }
-            }
-            
+    // every Sync log of the block in one request, by hash so they match its transactions
+    let hash = match block.hash {
+        Some(d) => d,
+        None => return (Vec::new(), next_nonces),
+    };
+    let filter = Filter::new().at_block_hash(hash).topic0(sync_topic);
+    let logs = match ws_provider.get_logs(&filter).await {
+        Ok(d) => d,
+        Err(error) => {
+            warn!("Failed on getting the logs of block {}: {}", number, error);
+            return (Vec::new(), next_nonces);
         }
+    };
+
+    let mut deltas = Vec::new();
+    {
+        let state_unlocked: tokio::sync::MutexGuard<State> = state.lock().await;
+        for log in logs {
+            let pointer = match state_unlocked.address_mapping.get(&log.address) {
+                Some(d) => *d,
+                None => continue,
+            };
+            let mut pair = match state_unlocked.pairs_mapping.get(&pointer) {
+                Some(p) => p.borrow_mut(),
+                None => continue,
+            };
+
+            pair.reserve0 = U256::from_big_endian(&log.data[0..32]);
+            pair.reserve1 = U256::from_big_endian(&log.data[32..]);
+            deltas.push(ReserveDelta {
+                address: log.address,
+                reserve0: pair.reserve0,
+                reserve1: pair.reserve1,
+            });
+        }
+    }
+
+    if deltas.is_empty() {
+        return (deltas, next_nonces);
+    }
+
+    if let Err(error) =
+        Storage::append_reserves(BINARY_CHECKPOINT_PATH, number.as_u64().into(), &deltas)
+    {
+        warn!("Failed on appending reserves of block {}: {}", number, error);
     }
+    (deltas, next_nonces)
 }
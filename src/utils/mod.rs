@@ -1,9 +1,15 @@
+use ethers::abi::{encode, Token};
 use ethers::prelude::*;
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     sync::Arc,
 };
 
+use crate::constants::SYNC_TOPIC;
+
+// Storage slot of UniswapV2Pair packing `reserve0`, `reserve1` and `blockTimestampLast`
+pub const RESERVES_SLOT: u64 = 8;
+
 pub async fn get_state_diffs(
     client: &Arc<Provider<Ws>>,
     meats: &Vec<Transaction>,
@@ -100,3 +106,77 @@ fn extract_logs(call_frame: &CallFrame, logs: &mut Vec<CallLogFrame>) {
     }
 }
 
+
+/// Sync logs of `tx` on top of `block_num`, read from the pairs' reserves slot in its state diff
+///
+/// Doesn't depend on the pairs emitting a standard Sync event, needs a node serving
+/// `trace_callMany`
+pub async fn get_reserve_logs(
+    client: &Arc<Provider<Ws>>,
+    tx: &Transaction,
+    block_num: BlockNumber,
+) -> Option<Vec<CallLogFrame>> {
+    let state_diffs = get_state_diffs(client, &vec![tx.clone()], block_num).await?;
+
+    Some(reserve_logs(&state_diffs))
+}
+
+/// A Sync log for every account whose reserves slot changed in `state_diffs`
+/// Accounts that aren't pairs are in there too, the caller keeps the pools it knows
+pub fn reserve_logs(state_diffs: &BTreeMap<Address, AccountDiff>) -> Vec<CallLogFrame> {
+    let slot = H256::from_low_u64_be(RESERVES_SLOT);
+
+    state_diffs
+        .iter()
+        .filter_map(|(address, account_diff)| {
+            let value = match account_diff.storage.get(&slot)? {
+                Diff::Born(d) => *d,
+                Diff::Changed(d) => d.to,
+                _ => return None,
+            };
+            let (reserve0, reserve1, _) = decode_reserves_slot(value);
+
+            Some(sync_log(*address, reserve0, reserve1))
+        })
+        .collect()
+}
+
+/// `(reserve0, reserve1, blockTimestampLast)` from the packed reserves slot
+/// reserve0 is in the low 112 bits, reserve1 in the next 112 and the timestamp in the top 32
+pub fn decode_reserves_slot(value: H256) -> (U256, U256, u32) {
+    let word = U256::from_big_endian(value.as_bytes());
+    let mask = (U256::one() << 112) - 1;
+
+    (word & mask, (word >> 112) & mask, (word >> 224).as_u32())
+}
+
+/// Sync log of `pair` as the pair contract emits it
+pub fn sync_log(pair: Address, reserve0: U256, reserve1: U256) -> CallLogFrame {
+    let sync_topic = H256::from_slice(&hex::decode(SYNC_TOPIC).unwrap());
+
+    CallLogFrame {
+        address: Some(pair),
+        topics: Some(vec![sync_topic]),
+        data: Some(encode(&[Token::Uint(reserve0), Token::Uint(reserve1)]).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_slot_unpacks() {
+        let reserve0 = U256::from(1_234_567_890_123u64);
+        let reserve1 = (U256::one() << 112) - 1;
+        let timestamp = 1_700_000_000u32;
+        let word = reserve0 | (reserve1 << 112) | (U256::from(timestamp) << 224);
+        let mut value = [0u8; 32];
+        word.to_big_endian(&mut value);
+
+        assert_eq!(
+            decode_reserves_slot(H256(value)),
+            (reserve0, reserve1, timestamp)
+        );
+    }
+}
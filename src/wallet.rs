@@ -0,0 +1,258 @@
+use ethers::prelude::*;
+use log::*;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::constants::WETH;
+use crate::contract_modules::uniswap_v2::pool_filter::parse_tokens;
+use crate::funding::{token_balance, Capital, Funding};
+use crate::helpers::address;
+
+// Below this much ETH the wallet can't pay for gas, 0.01 ETH
+const MIN_GAS_BALANCE: u64 = 10_000_000_000_000_000;
+// Ledger entries kept around, the totals cover every entry ever recorded
+const LEDGER_ENTRIES: usize = 1000;
+
+/// A cycle found and simulated, nothing is sent yet
+#[derive(Debug, Clone, Serialize)]
+pub struct PnlEntry {
+    // tx the cycle backruns
+    pub tx: TxHash,
+    pub block: U64,
+    pub pools: Vec<Address>,
+    pub funding: Funding,
+    pub amount_in: U256,
+    // WETH, after the funding cost
+    pub profit: I256,
+}
+
+/// What the cycles found would have made
+///
+/// Executed cycles, their gas and the balances they should leave belong here once cycles are
+/// sent
+#[derive(Debug, Clone, Default)]
+pub struct PnlLedger {
+    entries: VecDeque<PnlEntry>,
+    pub simulated: I256,
+    pub simulated_count: u64,
+}
+
+impl PnlLedger {
+    pub fn record(&mut self, entry: PnlEntry) {
+        self.simulated += entry.profit;
+        self.simulated_count += 1;
+
+        self.entries.push_back(entry);
+        if self.entries.len() > LEDGER_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Latest entries, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &PnlEntry> {
+        self.entries.iter()
+    }
+}
+
+/// Balances and nonce of the wallet at one block
+#[derive(Debug, Clone)]
+pub struct WalletSnapshot {
+    pub block: U64,
+    pub nonce: U256,
+    pub eth: U256,
+    pub tokens: HashMap<Address, U256>,
+}
+
+/// The trading wallet, as of the last confirmed block
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    pub address: Address,
+    pub block: U64,
+    pub nonce: U256,
+    // native balance, pays for gas
+    pub eth: U256,
+    // WETH and the base tokens
+    pub tokens: HashMap<Address, U256>,
+    pub min_gas_balance: U256,
+    pub ledger: PnlLedger,
+}
+
+impl Wallet {
+    /// Tracks WETH and the base tokens in `WALLET_TOKENS` (comma separated) of `owner`, gas is
+    /// paid for with at least `MIN_GAS_BALANCE` wei (0.01 ETH by default)
+    pub fn from_env(owner: Address) -> Self {
+        let mut tokens: HashMap<Address, U256> = match std::env::var("WALLET_TOKENS") {
+            Ok(value) => parse_tokens(&value)
+                .into_iter()
+                .map(|token| (token, U256::zero()))
+                .collect(),
+            Err(_) => HashMap::new(),
+        };
+        tokens.insert(address(WETH), U256::zero());
+        let min_gas_balance = match std::env::var("MIN_GAS_BALANCE") {
+            Ok(value) => U256::from_dec_str(&value).expect("invalid MIN_GAS_BALANCE"),
+            Err(_) => U256::from(MIN_GAS_BALANCE),
+        };
+
+        Self {
+            address: owner,
+            block: U64::zero(),
+            nonce: U256::zero(),
+            eth: U256::zero(),
+            tokens,
+            min_gas_balance,
+            ledger: PnlLedger::default(),
+        }
+    }
+
+    pub fn weth(&self) -> U256 {
+        self.tokens.get(&address(WETH)).copied().unwrap_or_default()
+    }
+
+    /// Whether the wallet can pay for sending a cycle, finding and simulating them costs nothing
+    pub fn can_pay_gas(&self) -> bool {
+        self.eth >= self.min_gas_balance
+    }
+
+    /// Takes the balances of a confirmed block
+    ///
+    /// Nothing is sent yet, so the nonce moving means something else uses the wallet
+    pub fn reconcile(&mut self, snapshot: WalletSnapshot) {
+        if snapshot.block <= self.block {
+            return;
+        }
+
+        if !self.block.is_zero() && snapshot.nonce != self.nonce {
+            warn!(
+                "Block {} | wallet nonce moved from {} to {} outside of the bot",
+                snapshot.block, self.nonce, snapshot.nonce
+            );
+        }
+        if self.can_pay_gas() && snapshot.eth < self.min_gas_balance {
+            warn!(
+                "Block {} | wallet ETH {} is below {}, cycles can't be sent",
+                snapshot.block, snapshot.eth, self.min_gas_balance
+            );
+        }
+
+        self.block = snapshot.block;
+        self.nonce = snapshot.nonce;
+        self.eth = snapshot.eth;
+        self.tokens = snapshot.tokens;
+    }
+
+    /// `capital` with the wallet's own WETH as inventory
+    ///
+    /// Gas isn't checked here, cycles are still found while the wallet can't pay for it, see
+    /// `can_pay_gas`
+    pub fn fundable(&self, capital: &Capital) -> Capital {
+        Capital {
+            inventory: self.weth(),
+            ..capital.clone()
+        }
+    }
+}
+
+/// Balances of `tokens` and nonce of `owner` at `block`
+pub async fn fetch_wallet<M: Middleware>(
+    middleware: &Arc<M>,
+    owner: Address,
+    tokens: &[Address],
+    block: U64,
+) -> Option<WalletSnapshot> {
+    let block_id = BlockId::Number(BlockNumber::Number(block));
+    let nonce = middleware
+        .get_transaction_count(owner, Some(block_id))
+        .await
+        .ok()?;
+    let eth = middleware.get_balance(owner, Some(block_id)).await.ok()?;
+
+    let mut balances = HashMap::new();
+    for token in tokens {
+        let balance = token_balance(middleware, *token, owner, Some(block_id)).await?;
+        balances.insert(*token, balance);
+    }
+
+    Some(WalletSnapshot {
+        block,
+        nonce,
+        eth,
+        tokens: balances,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(profit: i64) -> PnlEntry {
+        PnlEntry {
+            tx: TxHash::zero(),
+            block: U64::one(),
+            pools: Vec::new(),
+            funding: Funding::Inventory,
+            amount_in: U256::exp10(18),
+            profit: I256::from(profit),
+        }
+    }
+
+    fn snapshot(block: u64, nonce: u64, eth: u64, weth: u64) -> WalletSnapshot {
+        WalletSnapshot {
+            block: block.into(),
+            nonce: nonce.into(),
+            eth: eth.into(),
+            tokens: HashMap::from([(address(WETH), U256::from(weth))]),
+        }
+    }
+
+    #[test]
+    fn ledger_totals_every_entry() {
+        let mut ledger = PnlLedger::default();
+        ledger.record(entry(100));
+        ledger.record(entry(50));
+        ledger.record(entry(-20));
+
+        assert_eq!(ledger.simulated, I256::from(130));
+        assert_eq!(ledger.simulated_count, 3);
+        assert_eq!(ledger.entries().count(), 3);
+    }
+
+    #[test]
+    fn reconcile_takes_the_confirmed_balances() {
+        let mut wallet = Wallet::from_env(Address::zero());
+        wallet.reconcile(snapshot(10, 3, 1, 1000));
+
+        wallet.reconcile(snapshot(11, 4, 1, 1100));
+        assert_eq!(wallet.weth(), U256::from(1100));
+        assert_eq!(wallet.nonce, U256::from(4));
+
+        // an older block changes nothing
+        wallet.reconcile(snapshot(9, 0, 0, 0));
+        assert_eq!(wallet.block, U64::from(11));
+    }
+
+    #[test]
+    fn flash_funding_does_not_need_gas() {
+        let capital = Capital {
+            flash_swaps: true,
+            ..Capital::default()
+        };
+        let mut wallet = Wallet::from_env(Address::zero());
+        wallet.reconcile(snapshot(10, 0, 0, 1000));
+        assert!(!wallet.can_pay_gas());
+        assert_eq!(wallet.fundable(&capital).options().len(), 2);
+
+        // a wallet never read still finds flash funded cycles
+        let wallet = Wallet::from_env(Address::zero());
+        assert_eq!(
+            wallet.fundable(&capital).options(),
+            [(Funding::FlashSwap, U256::exp10(22))]
+        );
+
+        let mut wallet = Wallet::from_env(Address::zero());
+        wallet.reconcile(snapshot(11, 0, MIN_GAS_BALANCE, 1000));
+        assert!(wallet.can_pay_gas());
+        assert_eq!(wallet.fundable(&capital).inventory, U256::from(1000));
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+
+use arb_bot::states::block_policy::Chain;
+use ethers::prelude::*;
+
+// Consecutive headers of every chain, recorded by `record_headers`
+const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/headers");
+// Headers recorded per chain
+const HEADERS: u64 = 20;
+
+// (fixture name, chain, variable with the ws url of a node of it)
+const CHAINS: [(&str, Chain, &str); 3] = [
+    ("optimism", Chain::Optimism, "OPTIMISM_WSS"),
+    ("base", Chain::Base, "BASE_WSS"),
+    ("arbitrum", Chain::Arbitrum, "ARBITRUM_WSS"),
+];
+
+fn fixture_path(name: &str) -> String {
+    format!("{}/{}.json", FIXTURE_DIR, name)
+}
+
+fn load_headers(name: &str) -> Vec<Block<TxHash>> {
+    let file = File::open(fixture_path(name)).unwrap();
+    serde_json::from_reader(file).unwrap()
+}
+
+// Predicts every recorded header from the one before and compares with what was mined
+fn assert_predicts_recorded_headers(name: &str, chain: Chain) {
+    let headers = load_headers(name);
+    assert!(headers.len() > 1);
+    let policy = chain.policy();
+
+    for pair in headers.windows(2) {
+        let (latest, next) = (&pair[0], &pair[1]);
+        let predicted = policy.next_block(latest);
+
+        assert_eq!(Some(predicted.number), next.number);
+        assert_eq!(
+            Some(predicted.base_fee),
+            next.base_fee_per_gas,
+            "{name} block {:?}",
+            next.number
+        );
+        match chain {
+            // several blocks a second, the next one may already be in the next second
+            Chain::Arbitrum => assert!(next.timestamp - predicted.timestamp <= U256::one()),
+            _ => assert_eq!(predicted.timestamp, next.timestamp),
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs OPTIMISM_WSS, BASE_WSS and ARBITRUM_WSS, records tests/fixtures/headers"]
+async fn record_headers() {
+    dotenv::dotenv().ok();
+    std::fs::create_dir_all(FIXTURE_DIR).unwrap();
+
+    for (name, _, variable) in CHAINS {
+        let url = std::env::var(variable).unwrap_or_else(|_| panic!("missing {}", variable));
+        let provider = Provider::<Ws>::connect(url).await.unwrap();
+        let latest = provider.get_block_number().await.unwrap().as_u64();
+
+        let mut headers = Vec::new();
+        for number in latest - HEADERS + 1..=latest {
+            headers.push(provider.get_block(number).await.unwrap().unwrap());
+        }
+
+        let file = File::create(fixture_path(name)).unwrap();
+        serde_json::to_writer_pretty(file, &headers).unwrap();
+    }
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/headers/optimism.json, see record_headers"]
+fn optimism_predicts_recorded_headers() {
+    assert_predicts_recorded_headers("optimism", Chain::Optimism);
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/headers/base.json, see record_headers"]
+fn base_predicts_recorded_headers() {
+    assert_predicts_recorded_headers("base", Chain::Base);
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/headers/arbitrum.json, see record_headers"]
+fn arbitrum_predicts_recorded_headers() {
+    assert_predicts_recorded_headers("arbitrum", Chain::Arbitrum);
+}
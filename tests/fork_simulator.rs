@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use arb_bot::components::simulator::fixture::{FixtureAccount, FixtureState, RecordingFetcher};
+use arb_bot::components::simulator::fork_db::ForkDB;
+use arb_bot::components::simulator::fork_factory::ForkFactory;
+use arb_bot::components::simulator::state_fetcher::StateFetcher;
+use ethers::prelude::*;
+use revm::db::{CacheDB, EmptyDB};
+use revm::primitives::{ExecutionResult, Output, TransactTo, U256 as rU256};
+use tokio_util::sync::CancellationToken;
+
+// PUSH1 0 SLOAD PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN, returns slot 0
+const SLOT_ZERO_READER: [u8; 11] = [
+    0x60, 0x00, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+];
+
+fn fixture(contract: Address, slot_zero: H256) -> FixtureState {
+    let mut fixture = FixtureState::default();
+    fixture.accounts.insert(
+        contract,
+        FixtureAccount {
+            code: Bytes::from(SLOT_ZERO_READER.to_vec()),
+            storage: [(H256::zero(), slot_zero)].into(),
+            ..Default::default()
+        },
+    );
+    fixture
+}
+
+fn fork<F: StateFetcher>(fetcher: Arc<F>) -> ForkDB {
+    ForkFactory::new_sandbox_factory(
+        fetcher,
+        CacheDB::new(EmptyDB::default()),
+        None,
+        CancellationToken::new(),
+    )
+    .new_sandbox_fork()
+}
+
+fn call(fork: ForkDB, to: Address) -> Bytes {
+    let mut evm = revm::EVM::new();
+    evm.database(fork);
+    evm.env.tx.caller = Address::from_low_u64_be(1).0.into();
+    evm.env.tx.transact_to = TransactTo::Call(to.0.into());
+    evm.env.tx.gas_limit = 100000;
+    evm.env.tx.gas_price = rU256::ZERO;
+
+    match evm.transact_commit().expect("evm error") {
+        ExecutionResult::Success {
+            output: Output::Call(o),
+            ..
+        } => o.into(),
+        result => panic!("call to {to:?} failed: {result:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_runs_on_a_fixture() {
+    let contract = Address::from_low_u64_be(0x1000);
+    let slot_zero = H256::from_low_u64_be(42);
+
+    let output = call(fork(Arc::new(fixture(contract, slot_zero))), contract);
+    assert_eq!(output.as_ref(), slot_zero.as_bytes());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recorded_fixture_replays_the_simulation() {
+    let contract = Address::from_low_u64_be(0x1000);
+    let slot_zero = H256::from_low_u64_be(42);
+
+    let recorder = Arc::new(RecordingFetcher::new(fixture(contract, slot_zero)));
+    let recorded_output = call(fork(recorder.clone()), contract);
+
+    // only what the call read was recorded, and it's enough to run it again
+    let recorded = recorder.fixture();
+    assert_eq!(recorded.accounts[&contract].storage.len(), 1);
+
+    // unique per run, concurrent runs don't read each other's fixture
+    let path = std::env::temp_dir().join(format!(
+        "fork_simulator_fixture-{}.json",
+        std::process::id()
+    ));
+    let path = path.to_str().unwrap();
+    recorded.save_to_file(path).unwrap();
+    let replayed = FixtureState::load_from_file(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(call(fork(Arc::new(replayed)), contract), recorded_output);
+}
@@ -0,0 +1,217 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use arb_bot::components::simulator::fixture::{FixtureState, RecordingFetcher};
+use arb_bot::components::simulator::fork_db::ForkDB;
+use arb_bot::components::simulator::fork_factory::ForkFactory;
+use arb_bot::components::simulator::slot_finder::SlotFinder;
+use arb_bot::components::simulator::state_fetcher::StateFetcher;
+use arb_bot::contract_modules::uniswap_v2::bindings::uni_v2_router::{
+    GetAmountsOutCall, GetAmountsOutReturn, UniV2RouterCalls,
+};
+use arb_bot::contract_modules::uniswap_v2::constants::{
+    get_weth_address, tax_checker_controller_address,
+};
+use arb_bot::contract_modules::uniswap_v2::data_collector::tax_checker::{
+    get_token_tax, inject_tax_checker_code, insert_fake_approval,
+};
+use arb_bot::contract_modules::uniswap_v2::get_uni_v2;
+use arb_bot::contract_modules::uniswap_v2::router_calls::pair_address;
+use arb_bot::contract_modules::uniswap_v2::swap_math::get_amount_out;
+use arb_bot::contract_modules::uniswap_v2::types::TokenTax;
+use arb_bot::helpers::address;
+use ethers::abi::{parse_abi, AbiDecode, AbiEncode};
+use ethers::prelude::*;
+use ethers::utils::parse_ether;
+use revm::db::{CacheDB, EmptyDB};
+use revm::primitives::{ExecutionResult, Output, TransactTo, U256 as rU256};
+use tokio_util::sync::CancellationToken;
+
+const UNISWAP_ROUTER: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
+const SUSHISWAP_ROUTER: &str = "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F";
+
+// (router, pair, token0, token1)
+const FIXTURE_PAIRS: [(&str, &str, &str, &str); 3] = [
+    (
+        UNISWAP_ROUTER,
+        "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc",
+        "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+    ),
+    (
+        UNISWAP_ROUTER,
+        "0x0d4a11d5EEaaC28EC3F61d100daF4d40471f1852",
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+    ),
+    (
+        SUSHISWAP_ROUTER,
+        "0x397FF1542f962076d0BFE58eA045FfA2d347ACa0",
+        "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+    ),
+];
+
+// PAXG, takes a fee on every transfer, traded against WETH on Uniswap
+const TAXED_TOKEN: &str = "0x45804880De22913dAFE09f4980848ECE6EcbAf78";
+
+// Mainnet state read by the tests below, recorded by `swap_math_matches_router_on_fork`
+const FIXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mainnet.json");
+
+fn fork_factory<F: StateFetcher>(fetcher: Arc<F>, block: U64) -> ForkFactory {
+    ForkFactory::new_sandbox_factory(
+        fetcher,
+        CacheDB::new(EmptyDB::default()),
+        Some(block.into()),
+        CancellationToken::new(),
+    )
+}
+
+fn call(evm: &mut revm::EVM<ForkDB>, to: Address, data: Bytes) -> Bytes {
+    evm.env.tx.caller = tax_checker_controller_address();
+    evm.env.tx.transact_to = TransactTo::Call(to.0.into());
+    evm.env.tx.data = data.0;
+    evm.env.tx.gas_limit = 1000000;
+    evm.env.tx.gas_price = rU256::ZERO;
+    evm.env.tx.value = rU256::ZERO;
+
+    match evm.transact_commit().expect("evm error") {
+        ExecutionResult::Success {
+            output: Output::Call(o),
+            ..
+        } => o.into(),
+        result => panic!("call to {to:?} failed: {result:?}"),
+    }
+}
+
+// Compares `get_amount_out` with the routers' `getAmountsOut` on `FIXTURE_PAIRS`
+fn assert_swap_math_matches_router(fork_factory: &ForkFactory) {
+    let mut evm = revm::EVM::new();
+    evm.database(fork_factory.new_sandbox_fork());
+
+    let pair_contract = BaseContract::from(
+        parse_abi(&["function getReserves() external view returns (uint112,uint112,uint32)"])
+            .unwrap(),
+    );
+    let amounts = [
+        U256::one(),
+        U256::from(1000000),
+        parse_ether(1).unwrap(),
+        parse_ether(1000).unwrap(),
+    ];
+
+    for (router, pair, token0, token1) in FIXTURE_PAIRS {
+        let output = call(
+            &mut evm,
+            address(pair),
+            pair_contract.encode("getReserves", ()).unwrap(),
+        );
+        let (reserve0, reserve1, _): (U256, U256, u32) =
+            pair_contract.decode_output("getReserves", output).unwrap();
+
+        for (token_in, token_out, reserve_in, reserve_out) in [
+            (token0, token1, reserve0, reserve1),
+            (token1, token0, reserve1, reserve0),
+        ] {
+            for amount_in in amounts {
+                let calldata = UniV2RouterCalls::GetAmountsOut(GetAmountsOutCall {
+                    amount_in,
+                    path: vec![address(token_in), address(token_out)],
+                })
+                .encode();
+                let output = call(&mut evm, address(router), calldata.into());
+                let from_router = GetAmountsOutReturn::decode(output).unwrap().amounts[1];
+
+                let local =
+                    get_amount_out(amount_in, reserve_in, reserve_out, U256::from(9970)).unwrap();
+                assert_eq!(local, from_router, "pair {pair} amount_in {amount_in}");
+            }
+        }
+    }
+}
+
+// Runs the tax checker on `TAXED_TOKEN` against its Uniswap WETH pair
+async fn measure_taxed_token(fork_factory: &mut ForkFactory, block: U64) -> TokenTax {
+    let token = address(TAXED_TOKEN);
+    let weth = get_weth_address();
+    let uniswap = get_uni_v2()
+        .into_iter()
+        .find(|dex| dex.router == address(UNISWAP_ROUTER))
+        .unwrap();
+    let pair = pair_address(&uniswap, token, weth);
+
+    inject_tax_checker_code(fork_factory);
+    let mut slot_finder = SlotFinder::new();
+    assert!(insert_fake_approval(
+        weth,
+        pair,
+        fork_factory,
+        &mut slot_finder,
+        block
+    ));
+    assert!(insert_fake_approval(
+        token,
+        pair,
+        fork_factory,
+        &mut slot_finder,
+        block
+    ));
+
+    get_token_tax(
+        token,
+        weth,
+        pair,
+        fork_factory.new_sandbox_fork(),
+        fork_factory.new_sandbox_fork(),
+        block,
+        uniswap.fee,
+    )
+    .await
+    .expect("tax check failed")
+}
+
+fn assert_taxed(tax: &TokenTax) {
+    assert!(!tax.buy.is_zero(), "{tax:?}");
+    assert!(!tax.sell.is_zero(), "{tax:?}");
+    assert!(!tax.transfer.is_zero(), "{tax:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs NETWORK_WSS pointing at a mainnet node, records tests/fixtures/mainnet.json"]
+async fn swap_math_matches_router_on_fork() {
+    dotenv::dotenv().ok();
+    let wss_url = std::env::var("NETWORK_WSS").expect("missing NETWORK_WSS");
+    let provider = Provider::<Ws>::connect(wss_url).await.unwrap();
+    let block = provider.get_block_number().await.unwrap();
+
+    // everything both checks read is kept, so that they run again offline
+    let recorder = Arc::new(RecordingFetcher::new(provider));
+    let mut fork_factory = fork_factory(recorder.clone(), block);
+
+    assert_swap_math_matches_router(&fork_factory);
+    assert_taxed(&measure_taxed_token(&mut fork_factory, block).await);
+
+    let mut fixture = recorder.fixture();
+    fixture.block = block;
+    std::fs::create_dir_all(Path::new(FIXTURE_PATH).parent().unwrap()).unwrap();
+    fixture.save_to_file(FIXTURE_PATH).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs tests/fixtures/mainnet.json, see swap_math_matches_router_on_fork"]
+async fn swap_math_matches_router_on_fixture() {
+    let fixture = FixtureState::load_from_file(FIXTURE_PATH).unwrap();
+    let block = fixture.block;
+
+    assert_swap_math_matches_router(&fork_factory(Arc::new(fixture), block));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs tests/fixtures/mainnet.json, see swap_math_matches_router_on_fork"]
+async fn tax_checker_measures_taxed_token_on_fixture() {
+    let fixture = FixtureState::load_from_file(FIXTURE_PATH).unwrap();
+    let block = fixture.block;
+
+    let mut fork_factory = fork_factory(Arc::new(fixture), block);
+    assert_taxed(&measure_taxed_token(&mut fork_factory, block).await);
+}